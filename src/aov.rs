@@ -0,0 +1,232 @@
+use crate::filter::Filter;
+use crate::integrator::LightPasses;
+use crate::vec3::{Color, Point3, Vec3};
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+// What a camera sample found, for the render passes
+pub struct AovSample {
+    // Distance to the first hit along the ray, and its position and shading
+    // normal, all zero where the sample escaped
+    pub depth: f64,
+    pub position: Point3,
+    pub normal: Vec3,
+    // Material color at the first hit, or the background
+    pub albedo: Color,
+    // One more than the index of the object first hit in the world, zero
+    // for the background
+    pub object_id: f64,
+    pub passes: LightPasses,
+}
+
+// Render passes for compositing, taken from the same samples as the image
+// and filtered like it. Object IDs can't be averaged, so each pixel takes
+// that of the sample nearest its center.
+pub struct Aovs {
+    width: i32,
+    height: i32,
+    // Filtered sums per pixel, divided by the sums of the filter's weights
+    weights: Vec<f64>,
+    depth: Vec<f64>,
+    position: Vec<Point3>,
+    normal: Vec<Vec3>,
+    albedo: Vec<Color>,
+    emission: Vec<Color>,
+    direct: Vec<Color>,
+    indirect: Vec<Color>,
+    object_id: Vec<f64>,
+    // Squared distance from the pixel center of the sample `object_id`
+    // came from
+    nearest: Vec<f64>,
+}
+
+impl Aovs {
+    pub fn from(width: i32, height: i32) -> Self {
+        let size = (width.max(0) * height.max(0)) as usize;
+        Self {
+            width,
+            height,
+            weights: vec![0.0; size],
+            depth: vec![0.0; size],
+            position: vec![Point3::new(); size],
+            normal: vec![Vec3::new(); size],
+            albedo: vec![Color::new(); size],
+            emission: vec![Color::new(); size],
+            direct: vec![Color::new(); size],
+            indirect: vec![Color::new(); size],
+            object_id: vec![0.0; size],
+            nearest: vec![f64::INFINITY; size],
+        }
+    }
+
+    pub fn add_sample(
+        &mut self,
+        filter: &Filter,
+        x: f64,
+        y: f64,
+        sample: &AovSample,
+    ) {
+        // (x, y) is the raster position the sample was taken at
+        let width = self.width;
+        filter.splat(x, y, self.width, self.height, |i, j, weight| {
+            let index = (j * width + i) as usize;
+            self.weights[index] += weight;
+            self.depth[index] += weight * sample.depth;
+            self.position[index] += weight * sample.position;
+            self.normal[index] += weight * sample.normal;
+            self.albedo[index] += weight * sample.albedo;
+            self.emission[index] += weight * sample.passes.emission;
+            self.direct[index] += weight * sample.passes.direct;
+            self.indirect[index] += weight * sample.passes.indirect;
+        });
+
+        let i = (x + 0.5).floor() as i32;
+        let j = (y + 0.5).floor() as i32;
+        if i < 0 || j < 0 || i >= self.width || j >= self.height {
+            return;
+        }
+        let index = (j * width + i) as usize;
+        let distance = (x - i as f64).powi(2) + (y - j as f64).powi(2);
+        if distance < self.nearest[index] {
+            self.nearest[index] = distance;
+            self.object_id[index] = sample.object_id;
+        }
+    }
+
+    pub fn write_exr(
+        &self,
+        path: &str,
+        beauty: &[Color],
+        alpha: &[f64],
+    ) -> io::Result<()> {
+        // Writes the passes as layers of an OpenEXR image along with the
+        // image itself, `beauty`, and its `alpha`, which make up the default
+        // layer. As usual for OpenEXR the colors are premultiplied.
+        let average = |sums: &[f64]| -> Vec<f32> {
+            sums.iter()
+                .zip(&self.weights)
+                .map(|(sum, weight)| {
+                    if *weight != 0.0 {
+                        (sum / weight) as f32
+                    } else {
+                        0.0
+                    }
+                })
+                .collect()
+        };
+        let component = |values: &[Vec3], k: usize| -> Vec<f64> {
+            values.iter().map(|v| v.e[k]).collect()
+        };
+
+        let mut channels = Vec::new();
+        for (k, name) in ["R", "G", "B"].iter().enumerate() {
+            let values = beauty.iter().map(|c| c.e[k] as f32).collect();
+            channels.push((name.to_string(), values));
+        }
+        let alpha = alpha.iter().map(|a| *a as f32).collect();
+        channels.push(("A".to_string(), alpha));
+        let layers = [
+            ("position", ["X", "Y", "Z"], &self.position),
+            ("normal", ["X", "Y", "Z"], &self.normal),
+            ("albedo", ["R", "G", "B"], &self.albedo),
+            ("emission", ["R", "G", "B"], &self.emission),
+            ("direct", ["R", "G", "B"], &self.direct),
+            ("indirect", ["R", "G", "B"], &self.indirect),
+        ];
+        for (layer, names, values) in layers {
+            for (k, name) in names.iter().enumerate() {
+                let values = average(&component(values, k));
+                channels.push((format!("{}.{}", layer, name), values));
+            }
+        }
+        channels.push(("depth.Z".to_string(), average(&self.depth)));
+        let object_id = self.object_id.iter().map(|id| *id as f32).collect();
+        channels.push(("object_id.ID".to_string(), object_id));
+
+        let file = BufWriter::new(File::create(path)?);
+        write_exr(file, self.width, self.height, &mut channels)
+    }
+}
+
+fn write_exr<W: Write>(
+    mut out: W,
+    width: i32,
+    height: i32,
+    channels: &mut [(String, Vec<f32>)],
+) -> io::Result<()> {
+    // Single part scanline OpenEXR file of uncompressed 32 bit float
+    // channels, each holding the pixels in raster order
+    channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut header = Vec::new();
+    // Magic number, then version 2 with no flags set
+    header.extend_from_slice(&20000630_i32.to_le_bytes());
+    header.extend_from_slice(&2_i32.to_le_bytes());
+
+    let mut list = Vec::new();
+    for (name, _) in channels.iter() {
+        list.extend_from_slice(name.as_bytes());
+        list.push(0);
+        // Float pixels, not perceptually linear, sampled at every pixel
+        list.extend_from_slice(&2_i32.to_le_bytes());
+        list.extend_from_slice(&[0, 0, 0, 0]);
+        list.extend_from_slice(&1_i32.to_le_bytes());
+        list.extend_from_slice(&1_i32.to_le_bytes());
+    }
+    list.push(0);
+    attribute(&mut header, "channels", "chlist", &list);
+    attribute(&mut header, "compression", "compression", &[0]);
+    let mut window = Vec::new();
+    for value in [0, 0, width - 1, height - 1] {
+        window.extend_from_slice(&value.to_le_bytes());
+    }
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    // Increasing y
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1_f32.to_le_bytes(),
+    );
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1_f32.to_le_bytes(),
+    );
+    header.push(0);
+    out.write_all(&header)?;
+
+    // Table of where each scanline starts in the file
+    let line_size = channels.len() * width.max(0) as usize * 4;
+    let start = header.len() + height.max(0) as usize * 8;
+    for j in 0..height.max(0) as usize {
+        let offset = start + j * (8 + line_size);
+        out.write_all(&(offset as u64).to_le_bytes())?;
+    }
+
+    for j in 0..height {
+        out.write_all(&j.to_le_bytes())?;
+        out.write_all(&(line_size as i32).to_le_bytes())?;
+        for (_, values) in channels.iter() {
+            for i in 0..width {
+                let value = values[(j * width + i) as usize];
+                out.write_all(&value.to_le_bytes())?;
+            }
+        }
+    }
+    out.flush()
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
@@ -0,0 +1,777 @@
+use crate::camera::Camera;
+use crate::film::Film;
+use crate::hittable;
+use crate::hittable::{HitRecord, Hittable, HittableList};
+use crate::integrator::{Integrator, LightPasses};
+use crate::light::LightList;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::spectrum;
+use crate::spectrum::Wavelengths;
+use crate::utils;
+use crate::vec3;
+use crate::vec3::{Color, Point3, Vec3};
+
+// Bidirectional path tracer following Veach's thesis (1997) and pbrt's
+// formulation: a subpath is traced from the camera and another from a light,
+// and every pair of their prefixes is connected into a complete path, each
+// weighted against the other strategies that could have produced it with
+// the balance heuristic.
+#[derive(Debug)]
+pub struct Bdpt {
+    // Maximum number of bounces in a complete path
+    pub max_depth: i32,
+}
+
+impl Bdpt {
+    pub fn new() -> Self {
+        Self { max_depth: 10 }
+    }
+}
+
+#[derive(Clone)]
+enum Kind {
+    Camera,
+    Light,
+    // Surface hit, with the ray that arrived there
+    Surface(HitRecord, Ray),
+}
+
+#[derive(Clone)]
+struct Vertex {
+    kind: Kind,
+    p: Point3,
+    // Geometric normal, or zero for vertices that aren't on a surface
+    n: Vec3,
+    // Throughput of the subpath up to this vertex
+    beta: Color,
+    // Radiance emitted back along the arriving ray, for surfaces on lights
+    le: Color,
+    // Area densities of this vertex when generated from the previous vertex
+    // along its subpath, and from the next one with the path traced the
+    // other way
+    pdf_fwd: f64,
+    pdf_rev: f64,
+    // Found by a specular bounce, so it can't be connected to
+    delta: bool,
+    // Light vertex of a point, spot or directional light
+    delta_light: bool,
+    // Light vertex at infinity, on a directional light
+    infinite: bool,
+}
+
+impl Vertex {
+    fn endpoint(kind: Kind, p: Point3, n: Vec3, beta: Color) -> Self {
+        Self {
+            kind,
+            p,
+            n,
+            beta,
+            le: Color::new(),
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+            delta: false,
+            delta_light: false,
+            infinite: false,
+        }
+    }
+
+    fn on_surface(&self) -> bool {
+        !self.n.near_zero()
+    }
+
+    fn connectible(&self) -> bool {
+        match &self.kind {
+            Kind::Surface(rec, _) => {
+                rec.mat.as_ref().is_some_and(|mat| !mat.is_specular())
+            }
+            Kind::Light => !self.infinite,
+            Kind::Camera => true,
+        }
+    }
+
+    fn f(&self, next: &Vertex) -> Color {
+        // BSDF towards `next`, times the cosine at this vertex
+        match &self.kind {
+            Kind::Surface(rec, r_in) => match &rec.mat {
+                Some(mat) => mat.eval(r_in, rec, &(next.p - self.p)),
+                None => Color::new(),
+            },
+            _ => Color::new(),
+        }
+    }
+
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        // Solid angle density at this vertex to area density at `next`
+        if next.infinite {
+            return pdf;
+        }
+        let w = next.p - self.p;
+        let dist2 = w.length_squared();
+        if dist2 == 0.0 {
+            return 0.0;
+        }
+        let mut pdf = pdf / dist2;
+        if next.on_surface() {
+            pdf *= vec3::dot(&next.n, &w).abs() / dist2.sqrt();
+        }
+        pdf
+    }
+
+    fn pdf(
+        &self,
+        prev: Option<&Vertex>,
+        next: &Vertex,
+        camera: &Camera,
+        lights: &LightList,
+    ) -> f64 {
+        // Area density of sampling `next` from this vertex, having arrived
+        // from `prev`
+        match &self.kind {
+            Kind::Light => self.pdf_light(next, lights),
+            Kind::Camera => {
+                let ray = Ray::from(self.p, next.p - self.p);
+                self.convert_density(camera.pdf_we(&ray).1, next)
+            }
+            Kind::Surface(rec, _) => {
+                let (prev, mat) = match (prev, &rec.mat) {
+                    (Some(prev), Some(mat)) => (prev, mat),
+                    _ => return 0.0,
+                };
+                let pdf = bsdf_pdf(
+                    rec,
+                    mat.as_ref(),
+                    self.p - prev.p,
+                    next.p - self.p,
+                );
+                self.convert_density(pdf, next)
+            }
+        }
+    }
+
+    fn pdf_light(&self, next: &Vertex, lights: &LightList) -> f64 {
+        // Area density of `next` for a path leaving the light at this vertex
+        if self.infinite {
+            return 0.0;
+        }
+        let (_, pdf_dir) = lights.pdf_le(&self.p, &(next.p - self.p));
+        self.convert_density(pdf_dir, next)
+    }
+
+    fn pdf_light_origin(&self, next: &Vertex, lights: &LightList) -> f64 {
+        // Area density of this vertex as the start of a light subpath
+        if self.infinite {
+            return 0.0;
+        }
+        lights.pdf_le(&self.p, &(next.p - self.p)).0
+    }
+}
+
+fn bsdf_pdf(
+    rec: &HitRecord,
+    material: &dyn Material,
+    incoming: Vec3,
+    wi: Vec3,
+) -> f64 {
+    // Density of sampling `wi` for a ray arriving along `incoming`, which
+    // needn't be the ray that found the hit
+    let r_in = Ray::from(rec.p - incoming, incoming);
+    let outward = if rec.front_face {
+        rec.normal
+    } else {
+        -rec.normal
+    };
+    let mut rec = rec.clone();
+    rec.set_face_normal(&r_in, outward);
+    material.pdf(&r_in, &rec, &wi)
+}
+
+fn secondary_terminated(path: &[Vertex]) -> bool {
+    // Whether the subpath took a direction that only suits the hero
+    // wavelength, in spectral mode
+    path.iter().any(|v| match &v.kind {
+        Kind::Surface(_, ray) => ray
+            .wavelengths()
+            .is_some_and(|wavelengths| wavelengths.secondary_terminated()),
+        _ => false,
+    })
+}
+
+fn unoccluded(world: &HittableList, a: &Point3, b: &Point3) -> bool {
+    let to_b = *b - *a;
+    let distance = to_b.length();
+    let mut rec = HitRecord::new();
+    !world.hit(
+        &Ray::from(*a, to_b / distance),
+        hittable::Interval::from(0.001, distance - 0.001),
+        &mut rec,
+    )
+}
+
+// What the paths of one camera sample are traced through: the scene, the
+// camera and film at one end, and the sampler drawing their random numbers
+struct PathContext<'a> {
+    camera: &'a Camera,
+    world: &'a HittableList,
+    lights: &'a LightList,
+    film: &'a mut Film,
+    sampler: &'a mut dyn Sampler,
+}
+
+impl Bdpt {
+    fn random_walk(
+        &self,
+        ctx: &mut PathContext,
+        ray: Ray,
+        beta: Color,
+        pdf: f64,
+        max_vertices: usize,
+        path: &mut Vec<Vertex>,
+    ) -> Option<(Ray, Color)> {
+        // Extends `path` by sampling BSDFs from its last vertex, starting
+        // with `ray` sampled with solid angle density `pdf`. Returns the ray
+        // that escaped the scene, if any, with its throughput.
+        let mut ray = ray;
+        let mut beta = beta;
+        let mut pdf_fwd = pdf;
+
+        while path.len() < max_vertices {
+            let mut rec = HitRecord::new();
+            if !ctx.world.hit(
+                &ray,
+                hittable::Interval::from(0.001, f64::INFINITY),
+                &mut rec,
+            ) {
+                return Some((ray, beta));
+            }
+            let material = rec.mat.clone()?;
+
+            let prev = path.len() - 1;
+            let mut vertex = Vertex::endpoint(
+                Kind::Surface(rec.clone(), ray),
+                rec.p,
+                if rec.front_face {
+                    rec.normal
+                } else {
+                    -rec.normal
+                },
+                beta,
+            );
+            vertex.le = material.emitted(&ray, &rec);
+            vertex.pdf_fwd = path[prev].convert_density(pdf_fwd, &vertex);
+            path.push(vertex);
+
+            if path.len() >= max_vertices {
+                break;
+            }
+            let srec = match material.sample(&ray, &rec, ctx.sampler) {
+                Some(srec) => srec,
+                None => break,
+            };
+
+            // Density of sampling the way back, for the MIS weights
+            let current = path.len() - 1;
+            if srec.is_specular {
+                path[current].delta = true;
+                pdf_fwd = 0.0;
+                path[prev].pdf_rev = 0.0;
+            } else {
+                pdf_fwd = srec.pdf;
+                let pdf_rev = bsdf_pdf(
+                    &rec,
+                    material.as_ref(),
+                    -srec.scattered.direction(),
+                    -ray.direction(),
+                );
+                path[prev].pdf_rev =
+                    path[current].convert_density(pdf_rev, &path[prev]);
+            }
+
+            beta = beta * srec.attenuation;
+            ray = srec.scattered;
+        }
+        None
+    }
+
+    fn camera_subpath(
+        &self,
+        ctx: &mut PathContext,
+        ray: &Ray,
+        path: &mut Vec<Vertex>,
+    ) -> Color {
+        // Returns the radiance of the background if the subpath escapes
+        let beta = Color::from(1.0, 1.0, 1.0);
+        path.push(Vertex::endpoint(
+            Kind::Camera,
+            ray.origin(),
+            Vec3::new(),
+            beta,
+        ));
+        let (_, pdf_dir) = ctx.camera.pdf_we(ray);
+        let max_vertices = self.max_depth.max(0) as usize + 2;
+        match self.random_walk(ctx, *ray, beta, pdf_dir, max_vertices, path) {
+            Some((escaped, beta)) => spectrum::resolve(
+                &(beta * ctx.camera.background.value(&escaped)),
+                &escaped,
+            ),
+            None => Color::new(),
+        }
+    }
+
+    fn light_subpath(
+        &self,
+        ctx: &mut PathContext,
+        wavelengths: Option<Wavelengths>,
+        path: &mut Vec<Vertex>,
+    ) {
+        let (light, pmf) = match ctx
+            .lights
+            .sample_emitter(utils::random_double(ctx.sampler))
+        {
+            Some(choice) => choice,
+            None => return,
+        };
+        let le = match light.sample_le(ctx.sampler) {
+            Some(le) => le,
+            None => return,
+        };
+        if le.pdf_pos == 0.0 || le.pdf_dir == 0.0 || le.radiance.near_zero() {
+            return;
+        }
+
+        // The light subpath carries the same wavelengths as the camera's
+        let ray = le.ray.with_wavelengths(wavelengths);
+        let radiance = spectrum::illuminant(&le.radiance, &ray);
+        let mut vertex =
+            Vertex::endpoint(Kind::Light, ray.origin(), le.n, radiance);
+        vertex.pdf_fwd = pmf * le.pdf_pos;
+        vertex.delta_light = le.is_delta;
+        path.push(vertex);
+
+        let cos_theta = if le.n.near_zero() {
+            1.0
+        } else {
+            vec3::dot(&le.n, &ray.direction()).abs()
+        };
+        let beta = (cos_theta / (pmf * le.pdf_pos * le.pdf_dir)) * radiance;
+        let max_vertices = self.max_depth.max(0) as usize + 1;
+        self.random_walk(ctx, ray, beta, le.pdf_dir, max_vertices, path);
+    }
+
+    fn mis_weight(
+        &self,
+        camera_path: &[Vertex],
+        light_path: &[Vertex],
+        sampled: Option<&Vertex>,
+        camera: &Camera,
+        lights: &LightList,
+    ) -> f64 {
+        // Balance heuristic weight of the strategy using all of
+        // `camera_path` and `light_path`, where `sampled` replaces the last
+        // vertex of a subpath of length one.
+        let s = light_path.len();
+        let t = camera_path.len();
+        if s + t == 2 {
+            return 1.0;
+        }
+
+        let qs = match (s, sampled) {
+            (0, _) => None,
+            (1, Some(sampled)) => Some(sampled),
+            _ => Some(&light_path[s - 1]),
+        };
+        let pt = match (t, sampled) {
+            (1, Some(sampled)) => sampled,
+            _ => &camera_path[t - 1],
+        };
+        let qs_minus = if s > 1 {
+            Some(&light_path[s - 2])
+        } else {
+            None
+        };
+        let pt_minus = if t > 1 {
+            Some(&camera_path[t - 2])
+        } else {
+            None
+        };
+
+        // Densities and flags of each subpath, updated for the connection
+        let mut cam: Vec<(f64, f64, bool)> = camera_path
+            .iter()
+            .map(|v| (v.pdf_fwd, v.pdf_rev, v.delta))
+            .collect();
+        let mut lig: Vec<(f64, f64, bool)> = light_path
+            .iter()
+            .map(|v| (v.pdf_fwd, v.pdf_rev, v.delta))
+            .collect();
+        if let Some(sampled) = sampled {
+            let entry = (sampled.pdf_fwd, sampled.pdf_rev, sampled.delta);
+            if s == 1 {
+                lig[0] = entry;
+            } else {
+                cam[0] = entry;
+            }
+        }
+        let delta_light = match (s, sampled) {
+            (1, Some(sampled)) => sampled.delta_light,
+            (0, _) => false,
+            _ => light_path[0].delta_light,
+        };
+
+        // The connection vertices are never sampled specularly
+        cam[t - 1].2 = false;
+        cam[t - 1].1 = match qs {
+            Some(qs) => qs.pdf(qs_minus, pt, camera, lights),
+            None => match pt_minus {
+                Some(pt_minus) => pt.pdf_light_origin(pt_minus, lights),
+                None => 0.0,
+            },
+        };
+        if let Some(pt_minus) = pt_minus {
+            cam[t - 2].1 = match qs {
+                Some(qs) => pt.pdf(Some(qs), pt_minus, camera, lights),
+                None => pt.pdf_light(pt_minus, lights),
+            };
+        }
+        if let Some(qs) = qs {
+            lig[s - 1].2 = false;
+            lig[s - 1].1 = pt.pdf(pt_minus, qs, camera, lights);
+            if let Some(qs_minus) = qs_minus {
+                lig[s - 2].1 = qs.pdf(Some(pt), qs_minus, camera, lights);
+            }
+        }
+
+        // Ratios of the other strategies' densities to this one's
+        let remap0 = |f: f64| if f != 0.0 { f } else { 1.0 };
+        let mut sum_ri = 0.0;
+        let mut ri = 1.0;
+        for i in (1..t).rev() {
+            ri *= remap0(cam[i].1) / remap0(cam[i].0);
+            if !cam[i].2 && !cam[i - 1].2 {
+                sum_ri += ri;
+            }
+        }
+        ri = 1.0;
+        for i in (0..s).rev() {
+            ri *= remap0(lig[i].1) / remap0(lig[i].0);
+            let delta_prev = if i > 0 { lig[i - 1].2 } else { delta_light };
+            if !lig[i].2 && !delta_prev {
+                sum_ri += ri;
+            }
+        }
+        1.0 / (1.0 + sum_ri)
+    }
+
+    fn connect(
+        &self,
+        ctx: &mut PathContext,
+        camera_path: &[Vertex],
+        light_path: &[Vertex],
+        wavelengths: Option<Wavelengths>,
+    ) -> Color {
+        // Contribution of the strategy using all of both subpaths. Paths
+        // reaching the camera directly from the light subpath are splatted
+        // onto `film` instead of being returned.
+        let s = light_path.len();
+        let t = camera_path.len();
+        let mut sampled: Option<Vertex> = None;
+
+        // In spectral mode, only the hero wavelength counts once either
+        // subpath has terminated the others
+        let wavelengths = wavelengths.map(|wavelengths| {
+            if secondary_terminated(camera_path)
+                || secondary_terminated(light_path)
+            {
+                wavelengths.terminate_secondary()
+            } else {
+                wavelengths
+            }
+        });
+        let spectral_weight = match wavelengths {
+            Some(wavelengths) => wavelengths.weight(),
+            None => Color::from(1.0, 1.0, 1.0),
+        };
+
+        let contribution = if s == 0 {
+            // The camera subpath found a light by itself
+            let pt = &camera_path[t - 1];
+            pt.beta * pt.le
+        } else if t == 1 {
+            // Connect the light subpath to the camera
+            let qs = &light_path[s - 1];
+            if !qs.connectible() {
+                return Color::new();
+            }
+            let cs = match ctx.camera.sample_wi(&qs.p, ctx.sampler) {
+                Some(cs) => cs,
+                None => return Color::new(),
+            };
+            if cs.pdf <= 0.0 || cs.importance <= 0.0 {
+                return Color::new();
+            }
+            let vertex = Vertex::endpoint(
+                Kind::Camera,
+                cs.lens,
+                Vec3::new(),
+                (cs.importance / cs.pdf) * Color::from(1.0, 1.0, 1.0),
+            );
+            let contribution = qs.beta * qs.f(&vertex) * vertex.beta;
+            if contribution.near_zero()
+                || !unoccluded(ctx.world, &qs.p, &cs.lens)
+            {
+                return Color::new();
+            }
+            let weight = self.mis_weight(
+                camera_path,
+                light_path,
+                Some(&vertex),
+                ctx.camera,
+                ctx.lights,
+            );
+            // Splats skip the conversion done for returned radiance
+            let splat = weight * spectral_weight * contribution;
+            ctx.film.add_splat(
+                cs.raster.0,
+                cs.raster.1,
+                spectrum::to_rgb(&splat, wavelengths),
+            );
+            return Color::new();
+        } else if s == 1 {
+            // Sample a point on a light for the end of the camera subpath
+            let pt = &camera_path[t - 1];
+            if !pt.connectible() {
+                return Color::new();
+            }
+            let u = utils::random_double(ctx.sampler);
+            let (light, pmf) = match ctx.lights.sample(&pt.p, u) {
+                Some(choice) => choice,
+                None => return Color::new(),
+            };
+            let ls = match light.sample_li(&pt.p, ctx.sampler) {
+                Some(ls) => ls,
+                None => return Color::new(),
+            };
+            if ls.pdf <= 0.0 || ls.radiance.near_zero() {
+                return Color::new();
+            }
+
+            let shadow_ray =
+                Ray::from(pt.p, ls.wi).with_wavelengths(wavelengths);
+            let radiance = spectrum::illuminant(&ls.radiance, &shadow_ray);
+
+            let infinite = ls.distance.is_infinite();
+            let p = if infinite {
+                pt.p + ls.wi
+            } else {
+                pt.p + ls.distance * ls.wi
+            };
+            let mut vertex = Vertex::endpoint(
+                Kind::Light,
+                p,
+                ls.n,
+                radiance / (ls.pdf * pmf),
+            );
+            vertex.delta_light = ls.is_delta;
+            vertex.infinite = infinite;
+            vertex.pdf_fwd = vertex.pdf_light_origin(pt, ctx.lights);
+
+            let contribution = pt.beta * pt.f(&vertex) * vertex.beta;
+            let mut shadow_rec = HitRecord::new();
+            if contribution.near_zero()
+                || ctx.world.hit(
+                    &shadow_ray,
+                    hittable::Interval::from(0.001, ls.distance - 0.001),
+                    &mut shadow_rec,
+                )
+            {
+                return Color::new();
+            }
+            sampled = Some(vertex);
+            contribution
+        } else {
+            // Join the ends of both subpaths
+            let qs = &light_path[s - 1];
+            let pt = &camera_path[t - 1];
+            if !qs.connectible() || !pt.connectible() {
+                return Color::new();
+            }
+            let g = 1.0 / (qs.p - pt.p).length_squared();
+            let contribution = g * (qs.beta * qs.f(pt) * pt.f(qs) * pt.beta);
+            if contribution.near_zero() || !unoccluded(ctx.world, &qs.p, &pt.p)
+            {
+                return Color::new();
+            }
+            contribution
+        };
+
+        if contribution.near_zero() {
+            return Color::new();
+        }
+        self.mis_weight(
+            camera_path,
+            light_path,
+            sampled.as_ref(),
+            ctx.camera,
+            ctx.lights,
+        ) * spectral_weight
+            * contribution
+    }
+}
+
+impl Integrator for Bdpt {
+    fn li(
+        &self,
+        ray: &Ray,
+        camera: &Camera,
+        world: &HittableList,
+        lights: &LightList,
+        film: &mut Film,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        self.li_passes(ray, camera, world, lights, film, sampler)
+            .total()
+    }
+
+    fn li_passes(
+        &self,
+        ray: &Ray,
+        camera: &Camera,
+        world: &HittableList,
+        lights: &LightList,
+        film: &mut Film,
+        sampler: &mut dyn Sampler,
+    ) -> LightPasses {
+        // Light traced from the lights straight to the camera is splatted,
+        // and so missing from the passes
+        let mut ctx = PathContext {
+            camera,
+            world,
+            lights,
+            film,
+            sampler,
+        };
+        let mut camera_path = Vec::new();
+        let mut light_path = Vec::new();
+        let background = self.camera_subpath(&mut ctx, ray, &mut camera_path);
+        let mut passes = LightPasses::new();
+        passes.add(camera_path.len() as i32 - 1, background);
+        self.light_subpath(&mut ctx, ray.wavelengths(), &mut light_path);
+
+        for t in 1..=camera_path.len() {
+            for s in 0..=light_path.len() {
+                let depth = (s + t) as i32 - 2;
+                if (s == 1 && t == 1) || depth < 0 || depth > self.max_depth {
+                    continue;
+                }
+                let color = self.connect(
+                    &mut ctx,
+                    &camera_path[..t],
+                    &light_path[..s],
+                    ray.wavelengths(),
+                );
+                passes.add(depth, color);
+            }
+        }
+        passes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrator::SimplePathTracer;
+    use crate::light::{PointLight, SphereLight};
+    use crate::material::{DiffuseLight, Lambertian};
+    use crate::sphere::Sphere;
+    use std::rc::Rc;
+
+    fn diffuse_scene() -> (HittableList, LightList) {
+        // Diffuse balls on a diffuse floor, lit by a visible area light and
+        // a point light
+        let mut world = HittableList::new();
+        let mut lights = LightList::new();
+
+        let grey = Rc::new(Lambertian::from(Color::from(0.5, 0.5, 0.5)));
+        world.add(Box::new(Sphere::from(
+            Point3::from(0.0, -1000.0, 0.0),
+            1000.0,
+            grey,
+        )));
+        let red = Rc::new(Lambertian::from(Color::from(0.7, 0.2, 0.1)));
+        world.add(Box::new(Sphere::from(
+            Point3::from(-0.6, 0.5, 0.0),
+            0.5,
+            red,
+        )));
+        let blue = Rc::new(Lambertian::from(Color::from(0.1, 0.3, 0.8)));
+        world.add(Box::new(Sphere::from(
+            Point3::from(0.6, 0.5, -0.3),
+            0.5,
+            blue,
+        )));
+
+        let center = Point3::from(0.5, 2.0, 1.0);
+        let emit = Color::from(8.0, 8.0, 8.0);
+        world.add(Box::new(Sphere::from(
+            center,
+            0.4,
+            Rc::new(DiffuseLight::from(emit)),
+        )));
+        lights.add(Rc::new(SphereLight::from(center, 0.4, emit)));
+        lights.add(Rc::new(PointLight::from(
+            Point3::from(-1.5, 1.5, 1.5),
+            Color::from(2.0, 2.0, 2.0),
+        )));
+
+        (world, lights)
+    }
+
+    fn render_blocks(integrator: &dyn Integrator) -> Vec<Color> {
+        // Average color of each quarter of a small image
+        let (world, lights) = diffuse_scene();
+        let mut cam = Camera::new();
+        cam.aspect_ratio = 1.0;
+        cam.image_width = 12;
+        cam.samples_per_pixel = 256;
+        cam.vfov = 40.0;
+        cam.lookfrom = Point3::from(0.0, 1.5, 4.0);
+        cam.lookat = Point3::from(0.0, 0.5, 0.0);
+
+        let film = cam.render_film(&world, &lights, integrator);
+        let mut blocks = vec![Color::new(); 4];
+        for j in 0..film.height {
+            for i in 0..film.width {
+                let block =
+                    (2 * j / film.height * 2 + 2 * i / film.width) as usize;
+                blocks[block] += film.value(i, j);
+            }
+        }
+        let scale =
+            4.0 / (film.width * film.height * cam.samples_per_pixel) as f64;
+        blocks.into_iter().map(|block| scale * block).collect()
+    }
+
+    #[test]
+    fn bdpt_matches_path_tracer() {
+        let mut path_tracer = SimplePathTracer::new();
+        path_tracer.max_depth = 20;
+        let mut bdpt = Bdpt::new();
+        bdpt.max_depth = 20;
+
+        let expected = render_blocks(&path_tracer);
+        let actual = render_blocks(&bdpt);
+        for (expected, actual) in expected.iter().zip(actual.iter()) {
+            for k in 0..3 {
+                let error = (actual.e[k] - expected.e[k]).abs()
+                    / expected.e[k].max(1e-3);
+                assert!(
+                    error < 0.05,
+                    "BDPT gave {:?}, path tracing {:?}",
+                    actual,
+                    expected
+                );
+            }
+        }
+    }
+}
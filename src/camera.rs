@@ -1,18 +1,64 @@
-use crate::hittable::{HitRecord, Hittable, HittableList};
+use crate::aov::AovSample;
+use crate::checkpoint::{self, Checkpoint};
+use crate::denoise::{Denoiser, Features};
+use crate::film::Film;
+use crate::filter::Filter;
+use crate::hittable::{self, HitRecord, Hittable, HittableList};
+use crate::integrator::{Integrator, LightPasses};
+use crate::light::LightList;
 use crate::ray::Ray;
+use crate::sampler::{Sampler, Sampling};
+use crate::spectrum;
+use crate::spectrum::Wavelengths;
+use crate::tonemap::ToneMap;
+use crate::utils;
 use crate::utils::degrees_to_radians;
 use crate::vec3::{self};
 use crate::vec3::{Color, Point3, Vec3};
-use crate::{hittable, utils};
+use std::fs::File;
+use std::io::{self, BufWriter};
 use std::sync::{Arc, Mutex};
 
-use std::f64::INFINITY;
+use std::f64::consts::PI;
+
+#[derive(Debug)]
+pub enum Background {
+    // White to light blue vertical gradient
+    Sky,
+    Solid(Color),
+}
+
+impl Background {
+    pub fn value(&self, ray: &Ray) -> Color {
+        match self {
+            Background::Sky => {
+                let unit_direction = vec3::unit_vector(ray.direction());
+                let a = 0.5 * (unit_direction.y() + 1.0);
+                let color = (1.0 - a) * Color::from(1.0, 1.0, 1.0)
+                    + a * Color::from(0.5, 0.7, 1.0);
+                spectrum::illuminant(&color, ray)
+            }
+            Background::Solid(color) => spectrum::illuminant(color, ray),
+        }
+    }
+}
+
+pub struct CameraSample {
+    pub importance: f64,
+    // Solid angle density of `wi`
+    pub pdf: f64,
+    pub lens: Point3,
+    // Pixel coordinates the sample lands on
+    pub raster: (f64, f64),
+}
+
+// Samples per pixel the denoiser's features are averaged over at most
+const FEATURE_SAMPLES: i32 = 16;
 
 pub struct Camera {
     pub aspect_ratio: f64,
     pub image_width: i32,
     pub samples_per_pixel: i32,
-    pub max_depth: i32,
     pub vfov: f64, //
 
     pub lookfrom: Point3,
@@ -22,6 +68,65 @@ pub struct Camera {
     pub defocus_angle: f64,
     pub focus_dist: f64,
 
+    pub background: Background,
+
+    // Reconstruction filter: each sample counts toward the pixels within
+    // the filter's radius, weighted by the filter at its offset from their
+    // centers. SPPM always uses a box filter.
+    pub filter: Filter,
+
+    // Brightening of the image in stops, each doubling it, and the operator
+    // fitting it into the display's range
+    pub exposure: f64,
+    pub tone_map: ToneMap,
+
+    // Denoises the finished image, guided by the albedo, normal and depth
+    // of the first hits. Snapshots are left as they are.
+    pub denoiser: Option<Denoiser>,
+
+    // File `render` writes the image and its render passes to, as a multi
+    // layer OpenEXR image. The passes don't include light traced from the
+    // lights to the camera, and aren't saved in checkpoints, so a resumed
+    // render only has those of the samples taken since.
+    pub aov_path: Option<String>,
+
+    // Camera rays that miss everything see nothing instead of the
+    // background, and each pixel's alpha is the filtered fraction of its
+    // samples that hit something, so the image can be composited over
+    // another. The background still lights the scene.
+    pub transparent_background: bool,
+    // File `render` writes the image to as a PNG, with its alpha
+    pub png_path: Option<String>,
+
+    // Trace each sample at a few randomly chosen wavelengths instead of in
+    // RGB. Only meaningful for integrators that compute radiance; SPPM
+    // always renders in RGB.
+    pub spectral: bool,
+
+    // Seed the random numbers of every pixel sample are derived from
+    pub seed: u64,
+    pub sampling: Sampling,
+
+    // Adaptive sampling: a pixel stops taking samples once the standard
+    // error of its mean luminance falls below `adaptive_threshold` times
+    // the mean, after at least `adaptive_min_samples`. Zero disables it.
+    pub adaptive_threshold: f64,
+    pub adaptive_min_samples: i32,
+    // Write the number of samples each pixel took instead of the image
+    pub output_sample_map: bool,
+
+    // Progressive rendering: the image is rendered in passes of this many
+    // samples per pixel, so that it can be looked at before it's done. Zero
+    // takes every sample in a single pass.
+    pub pass_samples: i32,
+    // File `render` writes the image so far to after each pass
+    pub snapshot_path: Option<String>,
+    // File the progress of the render is saved to after each pass, and
+    // resumed from if it exists and was saved by a render with the same
+    // settings. A resumed render can be given more samples per pixel than
+    // it started with, unless it's stratified.
+    pub checkpoint_path: Option<String>,
+
     image_height: i32,
     pixel00_loc: Point3,
     pixel_delta_u: Vec3,
@@ -31,6 +136,11 @@ pub struct Camera {
     defocus_disk_u: Vec3,
     defocus_disk_v: Vec3,
 
+    // Area of the image at unit distance from the camera, and of the lens,
+    // which is 1 for a pinhole. Both normalize the camera's importance.
+    image_plane_area: f64,
+    lens_area: f64,
+
     // Camera frame basis Vectors
     u: Vec3,
     v: Vec3,
@@ -43,13 +153,37 @@ impl Camera {
             aspect_ratio: 16.0 / 9.0,
             image_width: 400,
             samples_per_pixel: 100,
-            max_depth: 10,
             vfov: 90.0,
             image_height: 225,
 
             defocus_angle: 0.0,
             focus_dist: 10.0,
 
+            background: Background::Sky,
+
+            filter: Filter::Box { radius: 0.5 },
+
+            exposure: 0.0,
+            tone_map: ToneMap::Clamp,
+
+            denoiser: None,
+            aov_path: None,
+            transparent_background: false,
+            png_path: None,
+
+            spectral: false,
+
+            seed: 0,
+            sampling: Sampling::Independent,
+
+            adaptive_threshold: 0.0,
+            adaptive_min_samples: 16,
+            output_sample_map: false,
+
+            pass_samples: 0,
+            snapshot_path: None,
+            checkpoint_path: None,
+
             lookfrom: Point3::from(0.0, 0.0, -1.0),
             lookat: Point3::new(),
             vup: Point3::from(0.0, 1.0, 0.0),
@@ -65,6 +199,9 @@ impl Camera {
 
             defocus_disk_u: Vec3::new(),
             defocus_disk_v: Vec3::new(),
+
+            image_plane_area: 1.0,
+            lens_area: 1.0,
         }
     }
 
@@ -114,101 +251,715 @@ impl Camera {
 
         self.defocus_disk_u = defocus_radius * self.u;
         self.defocus_disk_v = defocus_radius * self.v;
+
+        self.image_plane_area = viewport_width * viewport_height
+            / (self.focus_dist * self.focus_dist);
+        self.lens_area = if self.defocus_angle <= 0.0 {
+            1.0
+        } else {
+            PI * defocus_radius * defocus_radius
+        };
+    }
+
+    pub fn render(
+        &mut self,
+        world: &HittableList,
+        lights: &LightList,
+        integrator: &dyn Integrator,
+    ) {
+        let film = match self.snapshot_path.clone() {
+            Some(path) => self.render_progressive(
+                world,
+                lights,
+                integrator,
+                &mut |film, samples_per_pixel| {
+                    let written = File::create(&path).and_then(|file| {
+                        film.write_ppm_to(
+                            BufWriter::new(file),
+                            samples_per_pixel,
+                        )
+                    });
+                    if let Err(err) = written {
+                        log::warn!(
+                            "Failed to write snapshot {}: {}",
+                            path,
+                            err
+                        );
+                    }
+                },
+            ),
+            None => self.render_film(world, lights, integrator),
+        };
+        if self.output_sample_map {
+            film.write_sample_map();
+        } else {
+            film.write_ppm(self.samples_per_pixel);
+        }
+        if let Some(path) = &self.aov_path {
+            if let Err(err) = film.write_exr(path, self.samples_per_pixel) {
+                log::warn!("Failed to write render passes {}: {}", path, err);
+            }
+        }
+        if let Some(path) = &self.png_path {
+            if let Err(err) = film.write_png(path, self.samples_per_pixel) {
+                log::warn!("Failed to write {}: {}", path, err);
+            }
+        }
+
+        log::info!("Done");
     }
 
-    pub fn render(&mut self, world: &HittableList) {
+    pub fn render_film(
+        &mut self,
+        world: &HittableList,
+        lights: &LightList,
+        integrator: &dyn Integrator,
+    ) -> Film {
+        self.render_progressive(world, lights, integrator, &mut |_, _| {})
+    }
+
+    pub fn render_progressive(
+        &mut self,
+        world: &HittableList,
+        lights: &LightList,
+        integrator: &dyn Integrator,
+        on_pass: &mut dyn FnMut(&Film, i32),
+    ) -> Film {
+        // Renders the image in passes of `pass_samples` samples per pixel,
+        // handing the film to `on_pass` after each one along with the
+        // number of samples per pixel it is to be divided by so far.
         self.initialize();
 
-        println!("P3\n{} {}\n255", self.image_width, self.image_height);
+        let mut film = self.film();
+        let mut sampler =
+            self.sampling.sampler(self.samples_per_pixel, self.seed);
+        let pixels = (self.image_width * self.image_height) as usize;
+        // Filtered samples and their weights, for every pixel, and the
+        // filtered count of samples that hit something
+        let mut sums = vec![Color::new(); pixels];
+        let mut weights = vec![0.0; pixels];
+        let mut coverage = vec![0.0; pixels];
+        let mut luminances = vec![RunningVariance::new(); pixels];
+        let mut total_samples = 0;
+        let mut pass_start = 0;
+
+        let fingerprint = self.fingerprint(world, lights, integrator);
+        if let Some(checkpoint) = self.load_checkpoint(fingerprint) {
+            log::info!(
+                "Resuming from {} samples per pixel",
+                checkpoint.samples_per_pixel
+            );
+            sums = checkpoint.sums;
+            weights = checkpoint.weights;
+            coverage = checkpoint.coverage;
+            luminances = checkpoint.luminances;
+            total_samples = checkpoint.total_samples;
+            pass_start = checkpoint.samples_per_pixel;
+            film.restore_splats(&checkpoint.splats);
+        }
+
+        let pass_samples = if self.pass_samples > 0 {
+            self.pass_samples
+        } else {
+            self.samples_per_pixel
+        };
+        while pass_start < self.samples_per_pixel {
+            let pass_end =
+                (pass_start + pass_samples).min(self.samples_per_pixel);
+            log::info!(
+                r"Samples {} to {} of {} ",
+                pass_start,
+                pass_end,
+                self.samples_per_pixel
+            );
+
+            for j in 0..self.image_height {
+                log::info!(r"Scanlines remaining: {} ", self.image_height - j);
+                for i in 0..self.image_width {
+                    let index = (j * self.image_width + i) as usize;
+                    let luminance = &mut luminances[index];
+                    for s in pass_start..pass_end {
+                        if self.converged(luminance) {
+                            break;
+                        }
+                        sampler.start_pixel_sample(i, j, s);
+                        let wavelengths =
+                            self.sample_wavelengths(sampler.as_mut());
+                        let (x, y) = self.sample_raster(i, j, sampler.as_mut());
+                        let ray = self
+                            .get_ray_at(x, y, sampler.as_mut())
+                            .with_wavelengths(wavelengths);
+                        let mut passes = integrator
+                            .li_passes(
+                                &ray,
+                                self,
+                                world,
+                                lights,
+                                &mut film,
+                                sampler.as_mut(),
+                            )
+                            .map(|l| spectrum::to_rgb(l, wavelengths));
+                        // The integrator still runs for samples that see
+                        // the background, for the light it splats elsewhere
+                        let covered = !self.transparent_background
+                            || self.first_hit(&ray, world).is_some();
+                        if !covered {
+                            passes = LightPasses::new();
+                        }
+                        let color = passes.total();
+                        if film.has_aovs() {
+                            let sample = self.aov_sample(&ray, world, passes);
+                            film.add_aov_sample(x, y, &sample);
+                        }
+                        self.filter.splat(
+                            x,
+                            y,
+                            self.image_width,
+                            self.image_height,
+                            |i, j, weight| {
+                                let index = (j * self.image_width + i) as usize;
+                                sums[index] += weight * color;
+                                weights[index] += weight;
+                                if covered {
+                                    coverage[index] += weight;
+                                }
+                            },
+                        );
+                        luminance.add(vec3::luminance(&color));
+                        total_samples += 1;
+                    }
+                }
+            }
+
+            self.develop(
+                &mut film,
+                &sums,
+                &weights,
+                &luminances,
+                pass_end,
+                total_samples,
+            );
+            self.develop_alpha(&mut film, &weights, &coverage);
+            if let Some(path) = &self.checkpoint_path {
+                let checkpoint = Checkpoint {
+                    width: self.image_width,
+                    height: self.image_height,
+                    seed: self.seed,
+                    fingerprint,
+                    samples_per_pixel: pass_end,
+                    total_samples,
+                    sums: sums.clone(),
+                    weights: weights.clone(),
+                    coverage: coverage.clone(),
+                    luminances: luminances.clone(),
+                    splats: film.splats().to_vec(),
+                };
+                if let Err(err) = checkpoint.save(path) {
+                    log::warn!("Failed to write checkpoint {}: {}", path, err);
+                }
+            }
+            on_pass(&film, pass_end);
+            pass_start = pass_end;
+        }
 
+        // Developed for the samples asked for, which is what the film is
+        // divided by when written, even if a checkpoint held more
+        self.develop(
+            &mut film,
+            &sums,
+            &weights,
+            &luminances,
+            self.samples_per_pixel,
+            total_samples,
+        );
+        self.develop_alpha(&mut film, &weights, &coverage);
+        if let Some(denoiser) = &self.denoiser {
+            self.denoise(
+                denoiser,
+                &mut film,
+                world,
+                &luminances,
+                self.samples_per_pixel,
+            );
+        }
+        film
+    }
+
+    fn denoise(
+        &self,
+        denoiser: &Denoiser,
+        film: &mut Film,
+        world: &HittableList,
+        luminances: &[RunningVariance],
+        samples_per_pixel: i32,
+    ) {
+        log::info!("Denoising");
+        let scale = 1.0 / samples_per_pixel as f64;
+        let mut colors = Vec::with_capacity(luminances.len());
         for j in 0..self.image_height {
-            log::info!(r"Scanlines remaining: {} ", self.image_height - j);
             for i in 0..self.image_width {
-                let mut pixel_color = Color::new();
-                for _ in 0..self.samples_per_pixel {
-                    let ray = self.get_ray(i, j);
-                    pixel_color += self.ray_color(&ray, self.max_depth, &world);
+                colors.push(scale * film.value(i, j));
+            }
+        }
+        // Variance of each pixel's mean, taking pixels with too few
+        // samples to tell as hardly noisy
+        let variances: Vec<f64> = luminances
+            .iter()
+            .map(|luminance| {
+                if luminance.count() < 2 {
+                    0.0
+                } else {
+                    luminance.variance() / luminance.count() as f64
                 }
-                vec3::write_color(
-                    std::io::stdout(),
-                    &pixel_color,
-                    self.samples_per_pixel,
+            })
+            .collect();
+        let features = self.render_features(world);
+
+        let denoised = denoiser.denoise(
+            self.image_width,
+            self.image_height,
+            &colors,
+            &variances,
+            &features,
+        );
+        for j in 0..self.image_height {
+            for i in 0..self.image_width {
+                let index = (j * self.image_width + i) as usize;
+                film.set_value(
+                    i,
+                    j,
+                    samples_per_pixel as f64 * denoised[index],
                 );
             }
         }
+    }
+
+    fn render_features(&self, world: &HittableList) -> Vec<Features> {
+        // Albedo, normal and depth at the first hit of each pixel's camera
+        // samples, averaged. The rays are those of the render's first
+        // samples, traced again.
+        let samples = self.samples_per_pixel.clamp(1, FEATURE_SAMPLES);
+        let mut sampler =
+            self.sampling.sampler(self.samples_per_pixel, self.seed);
+        let mut features = Vec::new();
+        for j in 0..self.image_height {
+            for i in 0..self.image_width {
+                let mut albedo = Color::new();
+                let mut normal = Vec3::new();
+                let mut depth = 0.0;
+                let mut hits = 0;
+                for s in 0..samples {
+                    sampler.start_pixel_sample(i, j, s);
+                    self.sample_wavelengths(sampler.as_mut());
+                    let (x, y) = self.sample_raster(i, j, sampler.as_mut());
+                    let ray = self.get_ray_at(x, y, sampler.as_mut());
+
+                    let rec = match self.first_hit(&ray, world) {
+                        Some(rec) => rec,
+                        None => {
+                            albedo += self.background.value(&ray);
+                            continue;
+                        }
+                    };
+                    if let Some(material) = &rec.mat {
+                        albedo += material.albedo(&rec);
+                    }
+                    normal += rec.normal;
+                    depth += rec.t * ray.direction().length();
+                    hits += 1;
+                }
+                features.push(Features {
+                    albedo: albedo / samples as f64,
+                    normal: normal / samples as f64,
+                    depth: if hits > 0 { depth / hits as f64 } else { 0.0 },
+                });
+            }
+        }
+        features
+    }
 
-        log::info!("Done");
+    fn aov_sample(
+        &self,
+        ray: &Ray,
+        world: &HittableList,
+        passes: LightPasses,
+    ) -> AovSample {
+        // What the camera sample along `ray` found, for the render passes
+        let mut sample = AovSample {
+            depth: 0.0,
+            position: Point3::new(),
+            normal: Vec3::new(),
+            albedo: Color::new(),
+            object_id: 0.0,
+            passes,
+        };
+        // The albedo of the background is its color in RGB
+        let ray = Ray::from(ray.origin(), ray.direction());
+        match self.first_hit(&ray, world) {
+            Some(rec) => {
+                sample.depth = rec.t * ray.direction().length();
+                sample.position = rec.p;
+                sample.normal = rec.normal;
+                if let Some(material) = &rec.mat {
+                    sample.albedo = material.albedo(&rec);
+                }
+                sample.object_id = (rec.object + 1) as f64;
+            }
+            None => sample.albedo = self.background.value(&ray),
+        }
+        sample
+    }
+
+    fn first_hit(&self, ray: &Ray, world: &HittableList) -> Option<HitRecord> {
+        let mut rec = HitRecord::new();
+        if world.hit(
+            ray,
+            hittable::Interval::from(0.001, f64::INFINITY),
+            &mut rec,
+        ) {
+            Some(rec)
+        } else {
+            None
+        }
     }
 
-    fn get_ray(&self, i: i32, j: i32) -> Ray {
-        // Get a randomly-sampled camera ray for the pixel at location i, j, origination from
-        // the camera defocus disk
-        let pixel_center = self.pixel00_loc
-            + (i * self.pixel_delta_u)
-            + (j * self.pixel_delta_v);
+    fn develop(
+        &self,
+        film: &mut Film,
+        sums: &[Color],
+        weights: &[f64],
+        luminances: &[RunningVariance],
+        samples_per_pixel: i32,
+        total_samples: i64,
+    ) {
+        // Sets the film to the weighted average of the samples so far,
+        // times `samples_per_pixel` that it is to be divided by. This also
+        // makes up for pixels that stopped early.
+        for j in 0..self.image_height {
+            for i in 0..self.image_width {
+                let index = (j * self.image_width + i) as usize;
+                let count = luminances[index].count();
+                let color = if weights[index] > 0.0 {
+                    (samples_per_pixel as f64 / weights[index]) * sums[index]
+                } else {
+                    Color::new()
+                };
+                film.set_sample(i, j, color);
+                film.set_sample_count(i, j, count);
+            }
+        }
+
+        // Splats come from paths traced alongside each sample, so fewer
+        // samples leave them short by the same proportion
+        if total_samples > 0 {
+            let expected = samples_per_pixel as f64 * sums.len() as f64;
+            film.set_splat_scale(expected / total_samples as f64);
+        }
+    }
+
+    fn develop_alpha(
+        &self,
+        film: &mut Film,
+        weights: &[f64],
+        coverage: &[f64],
+    ) {
+        // Sets the film's alpha to the weighted fraction of the samples so
+        // far that hit something. Without a transparent background the
+        // image is opaque throughout, as the film starts out.
+        if !self.transparent_background {
+            return;
+        }
+        for j in 0..self.image_height {
+            for i in 0..self.image_width {
+                let index = (j * self.image_width + i) as usize;
+                // Negative filter lobes can take it past either end
+                let alpha = if weights[index] > 0.0 {
+                    (coverage[index] / weights[index]).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                film.set_alpha(i, j, alpha);
+            }
+        }
+    }
+
+    fn fingerprint(
+        &self,
+        world: &HittableList,
+        lights: &LightList,
+        integrator: &dyn Integrator,
+    ) -> u64 {
+        // Fingerprint of everything the samples of the render depend on,
+        // telling apart the checkpoints of other renders. Scenes are told
+        // apart by their size and where rays through a grid of pixels first
+        // hit them.
+        let mut settings = format!(
+            "{} {} {:?} {:?} {:?} {} {} {} {:?} {:?} {:?} {} {} {} {} {} {:?}",
+            self.image_width,
+            self.image_height,
+            self.lookfrom,
+            self.lookat,
+            self.vup,
+            self.vfov,
+            self.defocus_angle,
+            self.focus_dist,
+            self.background,
+            self.filter,
+            self.sampling,
+            self.spectral,
+            self.seed,
+            self.adaptive_threshold,
+            self.adaptive_min_samples,
+            self.transparent_background,
+            integrator,
+        );
+        // Stratified samples are laid out for the total count
+        if self.sampling == Sampling::Stratified {
+            settings += &format!(" {}", self.samples_per_pixel);
+        }
+        settings += &format!(" {} {}", world.objects.len(), lights.len());
+        let grid = 4;
+        for j in 0..grid {
+            for i in 0..grid {
+                let x =
+                    (i as f64 + 0.5) / grid as f64 * self.image_width as f64;
+                let y =
+                    (j as f64 + 0.5) / grid as f64 * self.image_height as f64;
+                let pixel = self.pixel00_loc
+                    + (x - 0.5) * self.pixel_delta_u
+                    + (y - 0.5) * self.pixel_delta_v;
+                let ray = Ray::from(self.center, pixel - self.center);
+                if let Some(rec) = self.first_hit(&ray, world) {
+                    settings += &format!(" {} {}", rec.object, rec.t);
+                }
+            }
+        }
+        checkpoint::fingerprint(&settings)
+    }
+
+    fn load_checkpoint(&self, fingerprint: u64) -> Option<Checkpoint> {
+        // Checkpoint to resume from, if there is one for this render
+        let path = self.checkpoint_path.as_ref()?;
+        let checkpoint = match Checkpoint::load(path) {
+            Ok(checkpoint) => checkpoint,
+            Err(err) => {
+                if err.kind() != io::ErrorKind::NotFound {
+                    log::warn!("Failed to read checkpoint {}: {}", path, err);
+                }
+                return None;
+            }
+        };
+        if checkpoint.width != self.image_width
+            || checkpoint.height != self.image_height
+            || checkpoint.seed != self.seed
+            || checkpoint.fingerprint != fingerprint
+        {
+            log::warn!("Ignoring checkpoint {} of another render", path);
+            return None;
+        }
+        Some(checkpoint)
+    }
+
+    fn converged(&self, luminance: &RunningVariance) -> bool {
+        if self.adaptive_threshold <= 0.0
+            || luminance.count() < self.adaptive_min_samples.max(2)
+        {
+            return false;
+        }
+        let standard_error =
+            (luminance.variance() / luminance.count() as f64).sqrt();
+        // Dark pixels are judged against a floor, as their relative error
+        // stays large even when the noise is invisible
+        standard_error <= self.adaptive_threshold * luminance.mean().max(0.01)
+    }
+
+    pub fn sample_wavelengths(
+        &self,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Wavelengths> {
+        // Wavelengths for a new camera sample, in spectral mode
+        if self.spectral {
+            Some(Wavelengths::sample(utils::random_double(sampler)))
+        } else {
+            None
+        }
+    }
+
+    pub fn image_height(&self) -> i32 {
+        self.image_height
+    }
 
-        let pixel_sample = pixel_center + self.pixel_sample_square();
+    pub fn film(&self) -> Film {
+        // Empty film for the image, splatting through the camera's filter,
+        // written with its exposure and tone mapping and keeping the render
+        // passes if they're wanted
+        let mut film = Film::from(self.image_width, self.image_height);
+        film.set_filter(self.filter);
+        film.set_tone_map(self.tone_map, self.exposure);
+        if self.aov_path.is_some() {
+            film.enable_aovs();
+        }
+        film
+    }
+
+    pub fn raster(&self, ray: &Ray) -> Option<(f64, f64)> {
+        // Continuous pixel coordinates where a ray leaving the lens crosses
+        // the image, with pixel centers at integer coordinates.
+        let cos_theta =
+            vec3::dot(&vec3::unit_vector(ray.direction()), &-self.w);
+        if cos_theta <= 0.0 {
+            return None;
+        }
+        let t = self.focus_dist / vec3::dot(&ray.direction(), &-self.w);
+        let offset = ray.at(t) - self.pixel00_loc;
+        let x = vec3::dot(&offset, &self.pixel_delta_u)
+            / self.pixel_delta_u.length_squared();
+        let y = vec3::dot(&offset, &self.pixel_delta_v)
+            / self.pixel_delta_v.length_squared();
+
+        let width = self.image_width as f64;
+        let height = self.image_height as f64;
+        if x < -0.5 || y < -0.5 || x >= width - 0.5 || y >= height - 0.5 {
+            return None;
+        }
+        Some((x, y))
+    }
+
+    pub fn importance(&self, ray: &Ray) -> f64 {
+        // Emitted importance We of a ray leaving the lens. It is normalized
+        // so that light paths splatted onto the image average to the same
+        // result as camera samples.
+        if self.raster(ray).is_none() {
+            return 0.0;
+        }
+        let cos_theta =
+            vec3::dot(&vec3::unit_vector(ray.direction()), &-self.w);
+        let cos2_theta = cos_theta * cos_theta;
+        1.0 / (self.image_plane_area * self.lens_area * cos2_theta * cos2_theta)
+    }
+
+    pub fn pdf_we(&self, ray: &Ray) -> (f64, f64) {
+        // Densities of the lens position (area) and direction (solid angle)
+        // with which `get_ray` produces a ray.
+        if self.raster(ray).is_none() {
+            return (0.0, 0.0);
+        }
+        let cos_theta =
+            vec3::dot(&vec3::unit_vector(ray.direction()), &-self.w);
+        (
+            1.0 / self.lens_area,
+            1.0 / (self.image_plane_area * cos_theta * cos_theta * cos_theta),
+        )
+    }
+
+    pub fn sample_wi(
+        &self,
+        p: &Point3,
+        sampler: &mut dyn Sampler,
+    ) -> Option<CameraSample> {
+        // Samples a point on the lens seen from `p`, for connecting paths
+        // to the camera.
+        let lens = if self.defocus_angle <= 0.0 {
+            self.center
+        } else {
+            self.defocus_disk_sample(sampler)
+        };
+        let ray = Ray::from(lens, *p - lens);
+        let (x, y) = self.raster(&ray)?;
+
+        let distance = ray.direction().length();
+        let cos_theta = vec3::dot(&ray.direction(), &-self.w) / distance;
+        Some(CameraSample {
+            importance: self.importance(&ray),
+            pdf: distance * distance / (cos_theta.abs() * self.lens_area),
+            lens,
+            raster: (x, y),
+        })
+    }
+
+    pub fn get_ray(&self, i: i32, j: i32, sampler: &mut dyn Sampler) -> Ray {
+        // Get a randomly-sampled camera ray for the pixel at location i, j
+        let (x, y) = self.sample_raster(i, j, sampler);
+        self.get_ray_at(x, y, sampler)
+    }
+
+    pub fn sample_raster(
+        &self,
+        i: i32,
+        j: i32,
+        sampler: &mut dyn Sampler,
+    ) -> (f64, f64) {
+        // Random raster position in the square of the pixel at location i, j
+        let (px, py) = sampler.get_2d();
+        (i as f64 + px - 0.5, j as f64 + py - 0.5)
+    }
+
+    pub fn get_ray_at(&self, x: f64, y: f64, sampler: &mut dyn Sampler) -> Ray {
+        // Ray through the raster position x, y, with pixel centers at
+        // integer coordinates, origination from the camera defocus disk
+        let pixel_sample = self.pixel00_loc
+            + (x * self.pixel_delta_u)
+            + (y * self.pixel_delta_v);
 
         let ray_origin = if self.defocus_angle <= 0.0 {
             self.center
         } else {
-            self.defocus_disk_sample()
+            self.defocus_disk_sample(sampler)
         };
         let ray_direction = pixel_sample - ray_origin;
 
         return Ray::from(ray_origin, ray_direction);
     }
 
-    fn defocus_disk_sample(&self) -> Point3 {
+    fn defocus_disk_sample(&self, sampler: &mut dyn Sampler) -> Point3 {
         //Returns a random point in the camera defocus disk
-        let p = vec3::random_in_unit_disk();
+        let p = vec3::random_in_unit_disk(sampler);
         return self.center
             + (p.x() * self.defocus_disk_u)
             + (p.y() * self.defocus_disk_v);
     }
+}
 
-    fn pixel_sample_square(&self) -> Vec3 {
-        //Returns a random point in thesquare surrounding a pixel at the origin
-        let px = -0.5 + crate::utils::random_double();
-        let py = -0.5 + crate::utils::random_double();
-        return (px * self.pixel_delta_u) + (py * self.pixel_delta_v);
-    }
-    fn ray_color(
-        &self,
-        ray: &Ray,
-        depth: i32,
-        world: &HittableList,
-    ) -> vec3::Color {
-        let mut rec = HitRecord::new();
+// Mean and variance of a stream of values, updated one value at a time with
+// Welford's algorithm.
+#[derive(Debug, Copy, Clone)]
+pub struct RunningVariance {
+    pub count: i32,
+    pub mean: f64,
+    // Sum of squared differences from the mean
+    pub m2: f64,
+}
 
-        // If we've exceeded the ray bounce limit, no more light is gathered.
-        if depth <= 0 {
-            return Color::from(0.0, 0.0, 0.0);
+impl RunningVariance {
+    fn new() -> Self {
+        Self {
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
         }
+    }
 
-        if world.hit(ray, hittable::Interval::from(0.001, INFINITY), &mut rec) {
-            // Temporarily take the material out and replace with None
-            if let Some(material) = rec.mat.take() {
-                let mut scattered = Ray::new();
-                let mut attenuation = Color::new();
+    fn add(&mut self, x: f64) {
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+    }
 
-                if material.scatter(ray, &rec, &mut attenuation, &mut scattered)
-                {
-                    // Put it back
-                    rec.mat = Some(material);
-                    return attenuation
-                        * self.ray_color(&scattered, depth - 1, world);
-                }
+    fn count(&self) -> i32 {
+        self.count
+    }
 
-                // put it back
-                rec.mat = Some(material);
-                return Color::new();
-            }
-            return Color::new();
+    fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    fn variance(&self) -> f64 {
+        // Sample variance
+        if self.count < 2 {
+            0.0
+        } else {
+            self.m2 / (self.count - 1) as f64
         }
-        let unit_direction = vec3::unit_vector(ray.direction());
-        let a = 0.5 * (unit_direction.y() + 1.0);
-        return (1.0 - a) * Color::from(1.0, 1.0, 1.0)
-            + a * Color::from(0.5, 0.7, 1.0);
     }
 }
@@ -0,0 +1,164 @@
+use crate::camera::RunningVariance;
+use crate::vec3::Color;
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+// Progress of a render, saved between passes so that it can be resumed if
+// the render is interrupted. Camera samples are seeded by their pixel and
+// index, so a resumed render takes exactly the samples it would have taken
+// without the interruption. It can also go on to take more, except with
+// stratified sampling, whose strata are laid out for the total.
+pub struct Checkpoint {
+    pub width: i32,
+    pub height: i32,
+    pub seed: u64,
+    // Of the settings of the render, from `fingerprint`
+    pub fingerprint: u64,
+    // Samples per pixel the render has got through, and samples actually
+    // taken over the whole image, which is less with adaptive sampling
+    pub samples_per_pixel: i32,
+    pub total_samples: i64,
+    // Per pixel, in raster order
+    pub sums: Vec<Color>,
+    pub weights: Vec<f64>,
+    pub coverage: Vec<f64>,
+    pub luminances: Vec<RunningVariance>,
+    pub splats: Vec<Color>,
+}
+
+// Start of every checkpoint file, with a version number
+const MAGIC: &[u8; 8] = b"RTCKPT04";
+
+impl Checkpoint {
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        // Written beside the previous checkpoint and then moved over it, so
+        // that dying halfway through leaves the previous one intact
+        let temp = format!("{}.tmp", path);
+        let mut out = BufWriter::new(File::create(&temp)?);
+        out.write_all(MAGIC)?;
+        write_i64(&mut out, self.width as i64)?;
+        write_i64(&mut out, self.height as i64)?;
+        write_i64(&mut out, self.seed as i64)?;
+        write_i64(&mut out, self.fingerprint as i64)?;
+        write_i64(&mut out, self.samples_per_pixel as i64)?;
+        write_i64(&mut out, self.total_samples)?;
+        for ((((sum, weight), coverage), luminance), splat) in self
+            .sums
+            .iter()
+            .zip(&self.weights)
+            .zip(&self.coverage)
+            .zip(&self.luminances)
+            .zip(&self.splats)
+        {
+            write_color(&mut out, sum)?;
+            write_f64(&mut out, *weight)?;
+            write_f64(&mut out, *coverage)?;
+            write_i64(&mut out, luminance.count as i64)?;
+            write_f64(&mut out, luminance.mean)?;
+            write_f64(&mut out, luminance.m2)?;
+            write_color(&mut out, splat)?;
+        }
+        out.into_inner()?.sync_all()?;
+        fs::rename(&temp, path)
+    }
+
+    pub fn load(path: &str) -> io::Result<Self> {
+        let mut input = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a checkpoint",
+            ));
+        }
+
+        let width = read_i64(&mut input)? as i32;
+        let height = read_i64(&mut input)? as i32;
+        let seed = read_i64(&mut input)? as u64;
+        let fingerprint = read_i64(&mut input)? as u64;
+        let samples_per_pixel = read_i64(&mut input)? as i32;
+        let total_samples = read_i64(&mut input)?;
+
+        let pixels = (width.max(0) * height.max(0)) as usize;
+        let mut sums = Vec::with_capacity(pixels);
+        let mut weights = Vec::with_capacity(pixels);
+        let mut coverage = Vec::with_capacity(pixels);
+        let mut luminances = Vec::with_capacity(pixels);
+        let mut splats = Vec::with_capacity(pixels);
+        for _ in 0..pixels {
+            sums.push(read_color(&mut input)?);
+            weights.push(read_f64(&mut input)?);
+            coverage.push(read_f64(&mut input)?);
+            luminances.push(RunningVariance {
+                count: read_i64(&mut input)? as i32,
+                mean: read_f64(&mut input)?,
+                m2: read_f64(&mut input)?,
+            });
+            splats.push(read_color(&mut input)?);
+        }
+
+        Ok(Self {
+            width,
+            height,
+            seed,
+            fingerprint,
+            samples_per_pixel,
+            total_samples,
+            sums,
+            weights,
+            coverage,
+            luminances,
+            splats,
+        })
+    }
+}
+
+pub fn fingerprint(settings: &str) -> u64 {
+    // 64 bit FNV-1a hash of a description of a render's settings, which
+    // unlike the standard library's hashers is the same in every build
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in settings.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// Numbers are stored as little endian 64 bit values
+
+fn write_i64<W: Write>(out: &mut W, value: i64) -> io::Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+fn write_f64<W: Write>(out: &mut W, value: f64) -> io::Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+fn write_color<W: Write>(out: &mut W, color: &Color) -> io::Result<()> {
+    for value in color.e {
+        write_f64(out, value)?;
+    }
+    Ok(())
+}
+
+fn read_i64<R: Read>(input: &mut R) -> io::Result<i64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(i64::from_le_bytes(bytes))
+}
+
+fn read_f64<R: Read>(input: &mut R) -> io::Result<f64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+fn read_color<R: Read>(input: &mut R) -> io::Result<Color> {
+    Ok(Color::from(
+        read_f64(input)?,
+        read_f64(input)?,
+        read_f64(input)?,
+    ))
+}
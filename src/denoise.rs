@@ -0,0 +1,175 @@
+use crate::vec3;
+use crate::vec3::{Color, Vec3};
+
+// What the camera samples of a pixel first hit, averaged over the samples.
+// These are nearly noise free even at low sample counts, and tell the
+// denoiser where the edges and textures of the scene are.
+#[derive(Debug, Copy, Clone)]
+pub struct Features {
+    // Material color, or the background where samples escaped
+    pub albedo: Color,
+    pub normal: Vec3,
+    // Distance to the hit, zero where every sample escaped
+    pub depth: f64,
+}
+
+// Feature guided non-local means filter, after Rousselle et al., "Robust
+// Denoising using Feature and Color Information" (2013). Each pixel is
+// averaged with the neighbours whose features match its own and whose
+// surroundings have similar colors, allowing for the noise in each. Colors
+// are divided by the albedo first, so that textures aren't blurred along
+// with the noise, and multiplied back afterwards.
+pub struct Denoiser {
+    // Half the width of the square of neighbours a pixel is averaged with
+    pub radius: i32,
+    // Half the width of the patches of color compared between pixels
+    pub patch_radius: i32,
+    // How far apart colors may be, relative to their noise, and still be
+    // averaged
+    pub color_strength: f64,
+    // Differences in albedo and normals, and in depth relative to the
+    // depth, that halve a neighbour's weight or so
+    pub albedo_sigma: f64,
+    pub normal_sigma: f64,
+    pub depth_sigma: f64,
+}
+
+// Albedos are kept above this when dividing by them
+const MIN_ALBEDO: f64 = 0.01;
+
+impl Denoiser {
+    pub fn new() -> Self {
+        Self {
+            radius: 7,
+            patch_radius: 1,
+            color_strength: 0.45,
+            albedo_sigma: 0.1,
+            normal_sigma: 0.3,
+            depth_sigma: 0.05,
+        }
+    }
+
+    pub fn denoise(
+        &self,
+        width: i32,
+        height: i32,
+        colors: &[Color],
+        variances: &[f64],
+        features: &[Features],
+    ) -> Vec<Color> {
+        // `colors` are the pixels of the image in raster order, and
+        // `variances` the variance of each, that is of the mean of its
+        // samples
+        let index = |i: i32, j: i32| (j * width + i) as usize;
+
+        // Color and variance of the light arriving at each pixel, without
+        // its albedo
+        let albedos: Vec<Color> = features
+            .iter()
+            .map(|f| {
+                Color::from(
+                    f.albedo.x().max(MIN_ALBEDO),
+                    f.albedo.y().max(MIN_ALBEDO),
+                    f.albedo.z().max(MIN_ALBEDO),
+                )
+            })
+            .collect();
+        let irradiance: Vec<Color> = colors
+            .iter()
+            .zip(&albedos)
+            .map(|(color, albedo)| {
+                Color::from(
+                    color.x() / albedo.x(),
+                    color.y() / albedo.y(),
+                    color.z() / albedo.z(),
+                )
+            })
+            .collect();
+        let irradiance_variance: Vec<f64> = variances
+            .iter()
+            .zip(&albedos)
+            .map(|(variance, albedo)| {
+                let luminance = vec3::luminance(albedo);
+                variance / (luminance * luminance)
+            })
+            .collect();
+
+        let k2 = self.color_strength * self.color_strength;
+        let patch_distance = |p: (i32, i32), q: (i32, i32)| {
+            // Mean over the patches around p and q of the squared color
+            // difference, less what the noise accounts for, relative to the
+            // noise
+            let mut total = 0.0;
+            let mut count = 0;
+            for dj in -self.patch_radius..=self.patch_radius {
+                for di in -self.patch_radius..=self.patch_radius {
+                    let (pi, pj) = (p.0 + di, p.1 + dj);
+                    let (qi, qj) = (q.0 + di, q.1 + dj);
+                    if pi < 0 || pj < 0 || pi >= width || pj >= height {
+                        continue;
+                    }
+                    if qi < 0 || qj < 0 || qi >= width || qj >= height {
+                        continue;
+                    }
+                    let (a, b) = (index(pi, pj), index(qi, qj));
+                    let (va, vb) =
+                        (irradiance_variance[a], irradiance_variance[b]);
+                    let difference = irradiance[a] - irradiance[b];
+                    let d2 = vec3::dot(&difference, &difference) / 3.0;
+                    total +=
+                        (d2 - (va + va.min(vb))) / (1e-10 + k2 * (va + vb));
+                    count += 1;
+                }
+            }
+            if count > 0 {
+                total / count as f64
+            } else {
+                0.0
+            }
+        };
+
+        let mut output = Vec::with_capacity(colors.len());
+        for j in 0..height {
+            for i in 0..width {
+                let p = index(i, j);
+                let mut sum = Color::new();
+                let mut total_weight = 0.0;
+                for qj in
+                    (j - self.radius).max(0)..=(j + self.radius).min(height - 1)
+                {
+                    for qi in (i - self.radius).max(0)
+                        ..=(i + self.radius).min(width - 1)
+                    {
+                        let q = index(qi, qj);
+                        let weight =
+                            self.feature_weight(&features[p], &features[q]);
+                        // Not worth comparing colors for
+                        if weight < 1e-3 {
+                            continue;
+                        }
+                        let weight = weight
+                            * (-patch_distance((i, j), (qi, qj)).max(0.0))
+                                .exp();
+                        sum += weight * irradiance[q];
+                        total_weight += weight;
+                    }
+                }
+                // The pixel itself always has a weight of one
+                output.push((sum / total_weight) * albedos[p]);
+            }
+        }
+        output
+    }
+
+    fn feature_weight(&self, p: &Features, q: &Features) -> f64 {
+        let albedo = p.albedo - q.albedo;
+        let normal = p.normal - q.normal;
+        let depth = (p.depth - q.depth) / (p.depth.max(q.depth) + 1e-10);
+        let distance = vec3::dot(&albedo, &albedo)
+            / (2.0 * self.albedo_sigma * self.albedo_sigma)
+            + vec3::dot(&normal, &normal)
+                / (2.0 * self.normal_sigma * self.normal_sigma)
+            + depth * depth / (2.0 * self.depth_sigma * self.depth_sigma);
+        (-distance).exp()
+    }
+}
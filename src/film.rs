@@ -0,0 +1,243 @@
+use crate::aov::{AovSample, Aovs};
+use crate::filter::Filter;
+use crate::png;
+use crate::tonemap::ToneMap;
+use crate::vec3;
+use crate::vec3::Color;
+
+use std::io::{self, Write};
+
+// Image being rendered. Camera samples are summed into the pixel they were
+// taken for, while splats are contributions that can land anywhere on the
+// image, such as light paths connected straight to the camera.
+pub struct Film {
+    pub width: i32,
+    pub height: i32,
+    pixels: Vec<Color>,
+    splats: Vec<Color>,
+    // Factor applied to the splats when reading the image
+    splat_scale: f64,
+    // Samples taken in each pixel, where they vary
+    sample_counts: Vec<i32>,
+    // Coverage of each pixel, from 0 where nothing is in front of the
+    // background to 1 where it's hidden. Colors are premultiplied by it.
+    alpha: Vec<f64>,
+    // Reconstruction filter splats are spread over nearby pixels with, and
+    // its integral, which the weights are divided by
+    filter: Filter,
+    filter_integral: f64,
+    // Applied to the image when it's written: a scale of `exposure` stops,
+    // then the tone mapping operator
+    exposure: f64,
+    tone_map: ToneMap,
+    // Render passes, if they're wanted
+    aovs: Option<Aovs>,
+}
+
+impl Film {
+    pub fn from(width: i32, height: i32) -> Self {
+        let size = (width.max(0) * height.max(0)) as usize;
+        Self {
+            width,
+            height,
+            pixels: vec![Color::new(); size],
+            splats: vec![Color::new(); size],
+            splat_scale: 1.0,
+            sample_counts: vec![0; size],
+            alpha: vec![1.0; size],
+            filter: Filter::Box { radius: 0.5 },
+            filter_integral: 1.0,
+            exposure: 0.0,
+            tone_map: ToneMap::Clamp,
+            aovs: None,
+        }
+    }
+
+    fn index(&self, i: i32, j: i32) -> usize {
+        (j * self.width + i) as usize
+    }
+
+    pub fn add_sample(&mut self, i: i32, j: i32, color: Color) {
+        let index = self.index(i, j);
+        self.pixels[index] += color;
+    }
+
+    pub fn set_sample(&mut self, i: i32, j: i32, color: Color) {
+        // Replaces the sum of the samples for a pixel
+        let index = self.index(i, j);
+        self.pixels[index] = color;
+    }
+
+    pub fn set_value(&mut self, i: i32, j: i32, color: Color) {
+        // Replaces the samples and splats for a pixel with their sum
+        let index = self.index(i, j);
+        self.pixels[index] = color;
+        self.splats[index] = Color::new();
+    }
+
+    pub fn set_alpha(&mut self, i: i32, j: i32, alpha: f64) {
+        let index = self.index(i, j);
+        self.alpha[index] = alpha;
+    }
+
+    pub fn alpha(&self, i: i32, j: i32) -> f64 {
+        self.alpha[self.index(i, j)]
+    }
+
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = filter;
+        self.filter_integral = filter.integral();
+    }
+
+    pub fn set_tone_map(&mut self, tone_map: ToneMap, exposure: f64) {
+        self.tone_map = tone_map;
+        self.exposure = exposure;
+    }
+
+    pub fn enable_aovs(&mut self) {
+        self.aovs = Some(Aovs::from(self.width, self.height));
+    }
+
+    pub fn has_aovs(&self) -> bool {
+        self.aovs.is_some()
+    }
+
+    pub fn add_aov_sample(&mut self, x: f64, y: f64, sample: &AovSample) {
+        if let Some(aovs) = &mut self.aovs {
+            aovs.add_sample(&self.filter, x, y, sample);
+        }
+    }
+
+    pub fn add_splat(&mut self, x: f64, y: f64, color: Color) {
+        // (x, y) is a raster position, with pixel centers at integer
+        // coordinates. The splat is shared by the pixels around it through
+        // the filter; shares outside the image are dropped.
+        let scale = 1.0 / self.filter_integral;
+        let splats = &mut self.splats;
+        let width = self.width;
+        self.filter
+            .splat(x, y, self.width, self.height, |i, j, weight| {
+                splats[(j * width + i) as usize] += (scale * weight) * color;
+            });
+    }
+
+    pub fn splats(&self) -> &[Color] {
+        &self.splats
+    }
+
+    pub fn restore_splats(&mut self, splats: &[Color]) {
+        // Replaces the splats, as saved from `splats`
+        self.splats.copy_from_slice(splats);
+    }
+
+    pub fn set_splat_scale(&mut self, factor: f64) {
+        self.splat_scale = factor;
+    }
+
+    pub fn set_sample_count(&mut self, i: i32, j: i32, count: i32) {
+        let index = self.index(i, j);
+        self.sample_counts[index] = count;
+    }
+
+    pub fn value(&self, i: i32, j: i32) -> Color {
+        // Sum of the samples and splats for a pixel, to be divided by the
+        // number of samples per pixel.
+        let index = self.index(i, j);
+        self.pixels[index] + self.splat_scale * self.splats[index]
+    }
+
+    pub fn write_ppm(&self, samples_per_pixel: i32) {
+        // Writes the averaged image to stdout
+        self.write_ppm_to(std::io::stdout(), samples_per_pixel)
+            .expect("Failed to write the image to stdout");
+    }
+
+    pub fn write_ppm_to<W: Write>(
+        &self,
+        mut out: W,
+        samples_per_pixel: i32,
+    ) -> io::Result<()> {
+        writeln!(out, "P3\n{} {}\n255", self.width, self.height)?;
+        let scale = self.exposure.exp2() / samples_per_pixel as f64;
+        for j in 0..self.height {
+            for i in 0..self.width {
+                let color = self.tone_map.apply(&(scale * self.value(i, j)));
+                vec3::write_color(&mut out, &color);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_exr(
+        &self,
+        path: &str,
+        samples_per_pixel: i32,
+    ) -> io::Result<()> {
+        // Writes the averaged image, unprocessed and with its alpha, as an
+        // OpenEXR file along with the render passes if there are any
+        let mut beauty = Vec::with_capacity(self.pixels.len());
+        for j in 0..self.height {
+            for i in 0..self.width {
+                beauty.push(self.value(i, j) / samples_per_pixel as f64);
+            }
+        }
+        match &self.aovs {
+            Some(aovs) => aovs.write_exr(path, &beauty, &self.alpha),
+            None => Aovs::from(self.width, self.height).write_exr(
+                path,
+                &beauty,
+                &self.alpha,
+            ),
+        }
+    }
+
+    pub fn write_png(
+        &self,
+        path: &str,
+        samples_per_pixel: i32,
+    ) -> io::Result<()> {
+        // Writes the image with its alpha, exposed and tone mapped as for
+        // `write_ppm_to`. PNG colors aren't premultiplied, so the alpha is
+        // divided out before tone mapping.
+        let scale = self.exposure.exp2() / samples_per_pixel as f64;
+        let max = |c: Color| c.x().max(c.y()).max(c.z()).clamp(0.0, 1.0);
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        for j in 0..self.height {
+            for i in 0..self.width {
+                let index = self.index(i, j);
+                // Negative filter lobes can leave colors below zero
+                let value = scale * self.value(i, j);
+                let value = Color::from(
+                    value.x().max(0.0),
+                    value.y().max(0.0),
+                    value.z().max(0.0),
+                );
+                // Splats don't come with coverage, and can land where camera
+                // samples barely covered the pixel or missed it. The alpha
+                // is raised to hold them, instead of dividing them up to
+                // white or dropping them.
+                let splat = (scale * self.splat_scale) * self.splats[index];
+                let alpha = self.alpha(i, j).max(max(splat));
+                let color = if alpha > 0.0 {
+                    self.tone_map.apply(&(value / alpha))
+                } else {
+                    Color::new()
+                };
+                let [r, g, b] = vec3::to_bytes(&color);
+                pixels.push([r, g, b, (255.0 * alpha).round() as u8]);
+            }
+        }
+        png::write_rgba(path, self.width, self.height, &pixels)
+    }
+
+    pub fn write_sample_map(&self) {
+        // Writes the number of samples taken in each pixel to stdout, as a
+        // grayscale image where white is the most sampled pixel
+        let max = self.sample_counts.iter().copied().max().unwrap_or(0);
+        println!("P3\n{} {}\n255", self.width, self.height);
+        for count in self.sample_counts.iter() {
+            let level = if max > 0 { 255 * count / max } else { 0 };
+            println!("{} {} {} ", level, level, level);
+        }
+    }
+}
@@ -9,8 +9,12 @@ pub struct HitRecord {
     pub p: Point3,
     pub normal: Vec3,
     pub t: f64,
+    pub u: f64,
+    pub v: f64,
     pub front_face: bool,
     pub mat: Option<Rc<dyn Material>>,
+    // Index of the object hit in the list searched
+    pub object: usize,
 }
 
 impl HitRecord {
@@ -19,8 +23,11 @@ impl HitRecord {
             p: Point3::new(),
             normal: Vec3::new(),
             t: 0.0,
+            u: 0.0,
+            v: 0.0,
             front_face: false,
             mat: None,
+            object: 0,
         }
     }
     pub fn set_face_normal(&mut self, ray: &Ray, outward_normal: Vec3) {
@@ -62,7 +69,7 @@ impl Hittable for HittableList {
         let mut hit_anything = false;
         let mut closest_so_far = ray_t.max;
 
-        for object in self.objects.iter() {
+        for (index, object) in self.objects.iter().enumerate() {
             if object.hit(
                 ray,
                 Interval::from(ray_t.min, closest_so_far),
@@ -70,6 +77,7 @@ impl Hittable for HittableList {
             ) {
                 hit_anything = true;
                 closest_so_far = temp_rec.t;
+                temp_rec.object = index;
                 // Neat little impl to avoid cloning
                 std::mem::swap(rec, &mut temp_rec);
             }
@@ -0,0 +1,403 @@
+use crate::camera::Camera;
+use crate::film::Film;
+use crate::hittable;
+use crate::hittable::{HitRecord, Hittable, HittableList};
+use crate::light::LightList;
+use crate::material::Material;
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::spectrum;
+use crate::utils;
+use crate::vec3;
+use crate::vec3::Color;
+
+use std::rc::Rc;
+
+// Light transport algorithm estimating the radiance carried along camera rays.
+// Its `Debug` form, with its settings, tells checkpoints of renders apart.
+pub trait Integrator: std::fmt::Debug {
+    // Radiance arriving at the ray's origin from its direction. Light
+    // reaching other pixels, as found by tracing paths from the lights, is
+    // splatted onto `film` instead.
+    fn li(
+        &self,
+        ray: &Ray,
+        camera: &Camera,
+        world: &HittableList,
+        lights: &LightList,
+        film: &mut Film,
+        sampler: &mut dyn Sampler,
+    ) -> Color;
+
+    // `li` split by the number of bounces along the paths. Integrators that
+    // don't follow light around, such as the debug views, report what they
+    // show as emission.
+    fn li_passes(
+        &self,
+        ray: &Ray,
+        camera: &Camera,
+        world: &HittableList,
+        lights: &LightList,
+        film: &mut Film,
+        sampler: &mut dyn Sampler,
+    ) -> LightPasses {
+        let mut passes = LightPasses::new();
+        passes.emission = self.li(ray, camera, world, lights, film, sampler);
+        passes
+    }
+}
+
+// Radiance reaching the camera, by the number of bounces on the way: light
+// emitted by the first surface hit or the background, light that reached
+// that surface straight from an emitter, and the rest.
+#[derive(Debug, Copy, Clone)]
+pub struct LightPasses {
+    pub emission: Color,
+    pub direct: Color,
+    pub indirect: Color,
+}
+
+impl LightPasses {
+    pub fn new() -> Self {
+        Self {
+            emission: Color::new(),
+            direct: Color::new(),
+            indirect: Color::new(),
+        }
+    }
+
+    pub fn add(&mut self, bounces: i32, color: Color) {
+        match bounces {
+            0 => self.emission += color,
+            1 => self.direct += color,
+            _ => self.indirect += color,
+        }
+    }
+
+    pub fn total(&self) -> Color {
+        self.emission + self.direct + self.indirect
+    }
+
+    pub fn map<F: Fn(&Color) -> Color>(&self, f: F) -> Self {
+        Self {
+            emission: f(&self.emission),
+            direct: f(&self.direct),
+            indirect: f(&self.indirect),
+        }
+    }
+}
+
+// Unidirectional path tracer with next-event estimation and multiple
+// importance sampling.
+#[derive(Debug)]
+pub struct SimplePathTracer {
+    pub max_depth: i32,
+    // Bounces before Russian roulette may terminate a path
+    pub rr_min_depth: i32,
+}
+
+impl SimplePathTracer {
+    pub fn new() -> Self {
+        Self {
+            max_depth: 10,
+            rr_min_depth: 3,
+        }
+    }
+}
+
+impl Integrator for SimplePathTracer {
+    fn li(
+        &self,
+        ray: &Ray,
+        camera: &Camera,
+        world: &HittableList,
+        lights: &LightList,
+        film: &mut Film,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        self.li_passes(ray, camera, world, lights, film, sampler)
+            .total()
+    }
+
+    fn li_passes(
+        &self,
+        ray: &Ray,
+        camera: &Camera,
+        world: &HittableList,
+        lights: &LightList,
+        _film: &mut Film,
+        sampler: &mut dyn Sampler,
+    ) -> LightPasses {
+        let mut passes = LightPasses::new();
+        // Product of the BSDF weights along the path so far
+        let mut throughput = Color::from(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+        // Density with which the previous bounce sampled `ray`, or None for
+        // camera rays and specular bounces, which light sampling can't
+        // reproduce.
+        let mut bsdf_pdf: Option<f64> = None;
+
+        // One iteration per bounce, up to the bounce limit
+        for depth in 0..self.max_depth {
+            let mut rec = HitRecord::new();
+            if !world.hit(
+                &ray,
+                hittable::Interval::from(0.001, f64::INFINITY),
+                &mut rec,
+            ) {
+                passes.add(depth, throughput * camera.background.value(&ray));
+                break;
+            }
+
+            let material = match rec.mat.clone() {
+                Some(material) => material,
+                None => break,
+            };
+
+            // Emission found by BSDF sampling is weighted against the chance
+            // that light sampling produced the same path.
+            let mut emitted = material.emitted(&ray, &rec);
+            if let Some(pdf) = bsdf_pdf {
+                let light_pdf =
+                    lights.pdf_li(&ray.origin(), &ray.direction(), &rec.p);
+                emitted = utils::power_heuristic(pdf, light_pdf) * emitted;
+            }
+            passes.add(depth, throughput * emitted);
+
+            if !material.is_specular() {
+                passes.add(
+                    depth + 1,
+                    throughput
+                        * sample_light(
+                            &ray,
+                            &rec,
+                            material.as_ref(),
+                            world,
+                            lights,
+                            sampler,
+                        ),
+                );
+            }
+
+            let srec = match material.sample(&ray, &rec, sampler) {
+                Some(srec) => srec,
+                None => break,
+            };
+            throughput = throughput * srec.attenuation;
+            bsdf_pdf = if srec.is_specular {
+                None
+            } else {
+                Some(srec.pdf)
+            };
+            ray = srec.scattered;
+
+            // Russian roulette: past the minimum depth, paths carrying little
+            // light are terminated at random and the survivors scaled up to
+            // keep the estimate unbiased.
+            if depth + 1 >= self.rr_min_depth {
+                let survive = throughput
+                    .x()
+                    .max(throughput.y())
+                    .max(throughput.z())
+                    .min(1.0);
+                if utils::random_double(sampler) >= survive {
+                    break;
+                }
+                throughput = throughput / survive;
+            }
+        }
+        passes.map(|color| spectrum::resolve(color, &ray))
+    }
+}
+
+pub fn sample_light(
+    ray: &Ray,
+    rec: &HitRecord,
+    material: &dyn Material,
+    world: &HittableList,
+    lights: &LightList,
+    sampler: &mut dyn Sampler,
+) -> Color {
+    // Direct lighting from one randomly chosen light, weighted against
+    // BSDF sampling with the power heuristic.
+    let (light, pmf) =
+        match lights.sample(&rec.p, utils::random_double(sampler)) {
+            Some(choice) => choice,
+            None => return Color::new(),
+        };
+    let ls = match light.sample_li(&rec.p, sampler) {
+        Some(ls) => ls,
+        None => return Color::new(),
+    };
+
+    let f = material.eval(ray, rec, &ls.wi);
+    if ls.pdf <= 0.0 || f.near_zero() {
+        return Color::new();
+    }
+
+    // Shadow ray towards the sampled point
+    let shadow_ray = Ray::from(rec.p, ls.wi);
+    let mut shadow_rec = HitRecord::new();
+    if world.hit(
+        &shadow_ray,
+        hittable::Interval::from(0.001, ls.distance - 0.001),
+        &mut shadow_rec,
+    ) {
+        return Color::new();
+    }
+
+    // Delta lights can't be hit by BSDF sampling, so they need no MIS
+    let light_pdf = pmf * ls.pdf;
+    let weight = if ls.is_delta {
+        1.0
+    } else {
+        utils::power_heuristic(light_pdf, material.pdf(ray, rec, &ls.wi))
+    };
+    let radiance = spectrum::illuminant(&ls.radiance, ray);
+    (weight / light_pdf) * (f * radiance)
+}
+
+// Ambient occlusion: the cosine-weighted fraction of the hemisphere above
+// the first hit that is unoccluded within `max_distance`.
+#[derive(Debug)]
+pub struct AmbientOcclusion {
+    pub samples: i32,
+    pub max_distance: f64,
+}
+
+impl AmbientOcclusion {
+    pub fn new() -> Self {
+        Self {
+            samples: 4,
+            max_distance: 1.0,
+        }
+    }
+}
+
+impl Integrator for AmbientOcclusion {
+    fn li(
+        &self,
+        ray: &Ray,
+        camera: &Camera,
+        world: &HittableList,
+        _lights: &LightList,
+        _film: &mut Film,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        let mut rec = HitRecord::new();
+        if !world.hit(
+            ray,
+            hittable::Interval::from(0.001, f64::INFINITY),
+            &mut rec,
+        ) {
+            return camera.background.value(ray);
+        }
+
+        // Cosine-weighted sampling cancels the cosine in the estimator
+        let uvw = Onb::build_from_w(&rec.normal);
+        let mut unoccluded = 0;
+        for _ in 0..self.samples {
+            let direction = uvw.local(&vec3::random_cosine_direction(sampler));
+            let mut occluder = HitRecord::new();
+            if !world.hit(
+                &Ray::from(rec.p, direction),
+                hittable::Interval::from(0.001, self.max_distance),
+                &mut occluder,
+            ) {
+                unoccluded += 1;
+            }
+        }
+        let visibility = unoccluded as f64 / self.samples.max(1) as f64;
+        Color::from(visibility, visibility, visibility)
+    }
+}
+
+// Quantity shown by `DebugShading`, taken from the first hit.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DebugView {
+    // Shading normal mapped from [-1, 1] to [0, 1]
+    Normal,
+    // Hit distance, black at the camera and white at `max_distance`
+    Depth,
+    // Surface coordinates as red and green
+    Uv,
+    // A distinct color per material instance
+    MaterialId,
+    // Green where the ray hit the outside of a surface, red the inside
+    FrontFace,
+}
+
+// False color views of the data in `HitRecord`, for checking scenes.
+#[derive(Debug)]
+pub struct DebugShading {
+    pub view: DebugView,
+    pub max_distance: f64,
+}
+
+impl DebugShading {
+    pub fn from(view: DebugView) -> Self {
+        Self {
+            view,
+            max_distance: 20.0,
+        }
+    }
+}
+
+impl Integrator for DebugShading {
+    fn li(
+        &self,
+        ray: &Ray,
+        _camera: &Camera,
+        world: &HittableList,
+        _lights: &LightList,
+        _film: &mut Film,
+        _sampler: &mut dyn Sampler,
+    ) -> Color {
+        let mut rec = HitRecord::new();
+        if !world.hit(
+            ray,
+            hittable::Interval::from(0.001, f64::INFINITY),
+            &mut rec,
+        ) {
+            return Color::new();
+        }
+
+        match self.view {
+            DebugView::Normal => {
+                0.5 * (rec.normal + Color::from(1.0, 1.0, 1.0))
+            }
+            DebugView::Depth => {
+                // Distance along the ray, which needn't be unit length
+                let depth =
+                    rec.t * ray.direction().length() / self.max_distance;
+                let depth = depth.clamp(0.0, 1.0);
+                Color::from(depth, depth, depth)
+            }
+            DebugView::Uv => Color::from(rec.u, rec.v, 0.0),
+            DebugView::MaterialId => match &rec.mat {
+                Some(mat) => id_color(Rc::as_ptr(mat) as *const () as usize),
+                None => Color::new(),
+            },
+            DebugView::FrontFace => {
+                if rec.front_face {
+                    Color::from(0.0, 1.0, 0.0)
+                } else {
+                    Color::from(1.0, 0.0, 0.0)
+                }
+            }
+        }
+    }
+}
+
+fn id_color(id: usize) -> Color {
+    // Scrambles an identifier into a bright, arbitrary color
+    let mut h = id as u64;
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51afd7ed558ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ceb9fe1a85ec53);
+    h ^= h >> 33;
+    let channel = |shift: u32| 0.2 + 0.8 * ((h >> shift) & 0xff) as f64 / 255.0;
+    Color::from(channel(0), channel(8), channel(16))
+}
@@ -1,16 +1,54 @@
+mod aov;
+mod bdpt;
 mod camera;
+mod checkpoint;
+mod denoise;
+mod film;
+mod filter;
 mod hittable;
+mod integrator;
+mod light;
+mod light_bvh;
 mod material;
+mod microfacet;
+mod mlt;
+mod onb;
+mod png;
+mod principled;
 mod ray;
+mod sampler;
+mod spectrum;
 mod sphere;
+mod sppm;
+mod texture;
+mod tonemap;
 mod utils;
 mod vec3;
 
-use camera::Camera;
-use material::{Dielectric, Lambertian, Metal};
+use bdpt::Bdpt;
+use camera::{Background, Camera};
+use denoise::Denoiser;
+use filter::Filter;
+use material::{
+    Conductor, Dielectric, DiffuseLight, Dispersion, Lambertian, Metal,
+    RoughDielectric, ThinFilm,
+};
+use mlt::Mlt;
+use principled::Principled;
+use sampler::{IndependentSampler, Sampling};
 use sphere::Sphere;
+use sppm::Sppm;
+use texture::{CheckerTexture, SolidColor};
+use tonemap::ToneMap;
 
 use hittable::HittableList;
+use integrator::{
+    AmbientOcclusion, DebugShading, DebugView, Integrator, SimplePathTracer,
+};
+use light::{
+    DirectionalLight, LightList, LightSampling, PointLight, SphereLight,
+    SpotLight,
+};
 use vec3::{Color, Point3, Vec3};
 
 use std::{
@@ -18,8 +56,9 @@ use std::{
     sync::{Arc, Mutex},
 };
 
-pub fn final_scene() {
+fn random_spheres_scene() -> (HittableList, LightList, Camera) {
     let mut world: HittableList = HittableList::new();
+    let mut sampler = IndependentSampler::new();
 
     let ground_material = Rc::new(Lambertian::from(Color::from(0.5, 0.5, 0.5)));
     world.add(Box::new(Sphere::from(
@@ -30,18 +69,19 @@ pub fn final_scene() {
 
     for a in -11..11 {
         for b in -11..11 {
-            let choose_mat = utils::random_double();
+            let choose_mat = utils::random_double(&mut sampler);
             let center = Point3::from(
-                a as f64 + 0.9 * utils::random_double(),
+                a as f64 + 0.9 * utils::random_double(&mut sampler),
                 0.2,
-                b as f64 + 0.9 * utils::random_double(),
+                b as f64 + 0.9 * utils::random_double(&mut sampler),
             );
             if (center - Point3::from(4.0, 0.2, 0.0)).length() > 0.9 {
                 let sphere_material: Rc<dyn material::Material>;
 
                 if choose_mat < 0.8 {
                     //Diffuse
-                    let albedo = Color::random() * Color::random();
+                    let albedo = Color::random(&mut sampler)
+                        * Color::random(&mut sampler);
 
                     sphere_material = Rc::new(Lambertian::from(albedo));
                     world.add(Box::new(Sphere::from(
@@ -51,8 +91,8 @@ pub fn final_scene() {
                     )));
                 } else if choose_mat < 0.95 {
                     // metal
-                    let albedo = Color::random_in(0.5, 1.0);
-                    let fuzz = utils::random_double_in(0.0, 0.5);
+                    let albedo = Color::random_in(0.5, 1.0, &mut sampler);
+                    let fuzz = utils::random_double_in(0.0, 0.5, &mut sampler);
                     sphere_material = Rc::new(Metal::from(albedo, fuzz));
                     world.add(Box::new(Sphere::from(
                         center,
@@ -89,8 +129,6 @@ pub fn final_scene() {
 
     cam.aspect_ratio = 16.0 / 9.0;
     cam.image_width = 1200;
-    cam.samples_per_pixel = 1000;
-    cam.max_depth = 50;
 
     cam.vfov = 20.0;
     cam.lookfrom = Point3::from(15.0, 2.0, 6.0);
@@ -100,7 +138,62 @@ pub fn final_scene() {
     cam.defocus_angle = 0.6;
     cam.focus_dist = 10.0;
 
-    cam.render(&world);
+    (world, LightList::new(), cam)
+}
+
+pub fn final_scene() {
+    let (world, lights, mut cam) = random_spheres_scene();
+
+    cam.samples_per_pixel = 1000;
+
+    let mut integrator = SimplePathTracer::new();
+    integrator.max_depth = 50;
+
+    cam.render(&world, &lights, &integrator);
+}
+
+pub fn final_scene_resumable() {
+    // `final_scene`, saving its progress to resume from if it's stopped.
+    // The finished render stays in the checkpoint, so running this again
+    // writes it out straight away, or adds to it given more samples; delete
+    // the checkpoint to start over.
+    let (world, lights, mut cam) = random_spheres_scene();
+
+    cam.samples_per_pixel = 1000;
+    cam.pass_samples = 50;
+    cam.checkpoint_path = Some("final_scene.checkpoint".to_string());
+
+    let mut integrator = SimplePathTracer::new();
+    integrator.max_depth = 50;
+
+    cam.render(&world, &lights, &integrator);
+}
+
+pub fn final_scene_progressive() {
+    // `final_scene`, keeping a preview of the render so far on disk
+    let (world, lights, mut cam) = random_spheres_scene();
+
+    cam.samples_per_pixel = 1000;
+    cam.pass_samples = 50;
+    cam.snapshot_path = Some("snapshot.ppm".to_string());
+
+    let mut integrator = SimplePathTracer::new();
+    integrator.max_depth = 50;
+
+    cam.render(&world, &lights, &integrator);
+}
+
+pub fn final_scene_preview() {
+    // Quick look at `final_scene`, denoised
+    let (world, lights, mut cam) = random_spheres_scene();
+
+    cam.samples_per_pixel = 32;
+    cam.denoiser = Some(Denoiser::new());
+
+    let mut integrator = SimplePathTracer::new();
+    integrator.max_depth = 50;
+
+    cam.render(&world, &lights, &integrator);
 }
 
 pub fn run() {
@@ -140,7 +233,6 @@ pub fn run() {
     let mut cam = Camera::new();
     cam.aspect_ratio = 16.0 / 9.0;
     cam.samples_per_pixel = 20;
-    cam.max_depth = 50;
 
     cam.vfov = 20.0;
     cam.lookfrom = Point3::from(10.0, 10.0, 1.0);
@@ -150,5 +242,944 @@ pub fn run() {
     cam.defocus_angle = 10.0;
     cam.focus_dist = 3.4;
 
-    cam.render(&world);
+    let mut integrator = SimplePathTracer::new();
+    integrator.max_depth = 50;
+
+    cam.render(&world, &LightList::new(), &integrator);
+}
+
+pub fn metals() {
+    let mut world: HittableList = HittableList::new();
+
+    let ground_material = Rc::new(Lambertian::from(Color::from(0.5, 0.5, 0.5)));
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.0, -1000.0, 0.0),
+        1000.0,
+        ground_material,
+    )));
+
+    let gold = Rc::new(Conductor::gold(0.2));
+    world.add(Box::new(Sphere::from(
+        Point3::from(-2.2, 1.0, 0.0),
+        1.0,
+        gold,
+    )));
+
+    let copper = Rc::new(Conductor::copper(0.05));
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.0, 1.0, 0.0),
+        1.0,
+        copper,
+    )));
+
+    let aluminum = Rc::new(Conductor::aluminum(0.5));
+    world.add(Box::new(Sphere::from(
+        Point3::from(2.2, 1.0, 0.0),
+        1.0,
+        aluminum,
+    )));
+
+    let mut cam = Camera::new();
+    cam.aspect_ratio = 16.0 / 9.0;
+    cam.image_width = 600;
+    cam.samples_per_pixel = 200;
+
+    cam.vfov = 30.0;
+    cam.lookfrom = Point3::from(0.0, 2.0, 9.0);
+    cam.lookat = Point3::from(0.0, 1.0, 0.0);
+    cam.vup = Vec3::from(0.0, 1.0, 0.0);
+
+    let mut integrator = SimplePathTracer::new();
+    integrator.max_depth = 50;
+
+    cam.render(&world, &LightList::new(), &integrator);
+}
+
+pub fn frosted_glass() {
+    let mut world: HittableList = HittableList::new();
+
+    let ground_material = Rc::new(Lambertian::from(Color::from(0.5, 0.5, 0.5)));
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.0, -1000.0, 0.0),
+        1000.0,
+        ground_material,
+    )));
+
+    let backdrop = Rc::new(Lambertian::from(Color::from(0.8, 0.3, 0.1)));
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.0, 1.0, -4.0),
+        1.0,
+        backdrop,
+    )));
+
+    let clear = Rc::new(Dielectric::from(1.5));
+    world.add(Box::new(Sphere::from(
+        Point3::from(-2.2, 1.0, 0.0),
+        1.0,
+        clear,
+    )));
+
+    let frosted =
+        Rc::new(RoughDielectric::from(1.5, 0.3, Color::from(1.0, 1.0, 1.0)));
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.0, 1.0, 0.0),
+        1.0,
+        frosted,
+    )));
+
+    let tinted =
+        Rc::new(RoughDielectric::from(1.5, 0.6, Color::from(0.6, 0.9, 0.7)));
+    world.add(Box::new(Sphere::from(
+        Point3::from(2.2, 1.0, 0.0),
+        1.0,
+        tinted,
+    )));
+
+    let mut cam = Camera::new();
+    cam.aspect_ratio = 16.0 / 9.0;
+    cam.image_width = 600;
+    cam.samples_per_pixel = 200;
+
+    cam.vfov = 30.0;
+    cam.lookfrom = Point3::from(0.0, 2.0, 9.0);
+    cam.lookat = Point3::from(0.0, 1.0, 0.0);
+    cam.vup = Vec3::from(0.0, 1.0, 0.0);
+
+    let mut integrator = SimplePathTracer::new();
+    integrator.max_depth = 50;
+
+    cam.render(&world, &LightList::new(), &integrator);
+}
+
+pub fn glass() {
+    let mut world: HittableList = HittableList::new();
+
+    let ground_material = Rc::new(Lambertian::from(Color::from(0.5, 0.5, 0.5)));
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.0, -1000.0, 0.0),
+        1000.0,
+        ground_material,
+    )));
+
+    // Thick bottle glass, tinted green by absorption along the path
+    let bottle =
+        Rc::new(Dielectric::absorbing(1.5, Color::from(0.8, 0.1, 0.6)));
+    world.add(Box::new(Sphere::from(
+        Point3::from(-2.2, 1.0, 0.0),
+        1.0,
+        bottle,
+    )));
+
+    let small_bottle = Rc::new(Dielectric::dispersive(
+        Dispersion::Cauchy {
+            a: 1.5046,
+            b: 0.00420,
+        },
+        Color::from(0.8, 0.1, 0.6),
+    ));
+    world.add(Box::new(Sphere::from(
+        Point3::from(-0.6, 0.3, 1.5),
+        0.3,
+        small_bottle,
+    )));
+
+    let diamond =
+        Rc::new(Dielectric::dispersive(Dispersion::diamond(), Color::new()));
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.0, 1.0, 0.0),
+        1.0,
+        diamond,
+    )));
+
+    let crown =
+        Rc::new(Dielectric::dispersive(Dispersion::bk7(), Color::new()));
+    world.add(Box::new(Sphere::from(
+        Point3::from(2.2, 1.0, 0.0),
+        1.0,
+        crown,
+    )));
+
+    let mut cam = Camera::new();
+    cam.aspect_ratio = 16.0 / 9.0;
+    cam.image_width = 600;
+    cam.samples_per_pixel = 200;
+    // Most of the frame is plain sky and ground, which settle early
+    cam.adaptive_threshold = 0.01;
+
+    cam.vfov = 30.0;
+    cam.lookfrom = Point3::from(0.0, 2.0, 9.0);
+    cam.lookat = Point3::from(0.0, 1.0, 0.0);
+    cam.vup = Vec3::from(0.0, 1.0, 0.0);
+
+    let mut integrator = SimplePathTracer::new();
+    integrator.max_depth = 50;
+
+    cam.render(&world, &LightList::new(), &integrator);
+}
+
+pub fn principled() {
+    let mut world: HittableList = HittableList::new();
+
+    let checker = Rc::new(CheckerTexture::from(
+        0.5,
+        Rc::new(SolidColor::from(Color::from(0.2, 0.3, 0.1))),
+        Rc::new(SolidColor::from(Color::from(0.9, 0.9, 0.9))),
+    ));
+    let mut ground_material = Principled::new();
+    ground_material.base_color = checker;
+    ground_material.roughness = Rc::new(SolidColor::scalar(0.8));
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.0, -1000.0, 0.0),
+        1000.0,
+        Rc::new(ground_material),
+    )));
+
+    // Glossy red plastic
+    let mut plastic = Principled::new();
+    plastic.base_color = Rc::new(SolidColor::from(Color::from(0.7, 0.1, 0.1)));
+    plastic.roughness = Rc::new(SolidColor::scalar(0.2));
+
+    // Brushed, anisotropic metal
+    let mut brushed = Principled::new();
+    brushed.base_color = Rc::new(SolidColor::from(Color::from(0.9, 0.9, 0.9)));
+    brushed.metallic = Rc::new(SolidColor::scalar(1.0));
+    brushed.roughness = Rc::new(SolidColor::scalar(0.4));
+    brushed.anisotropic = Rc::new(SolidColor::scalar(0.8));
+
+    // Car paint with a clearcoat layer
+    let mut paint = Principled::new();
+    paint.base_color = Rc::new(SolidColor::from(Color::from(0.05, 0.1, 0.4)));
+    paint.metallic = Rc::new(SolidColor::scalar(0.5));
+    paint.roughness = Rc::new(SolidColor::scalar(0.5));
+    paint.clearcoat = Rc::new(SolidColor::scalar(1.0));
+
+    // Velvet like fabric
+    let mut fabric = Principled::new();
+    fabric.base_color = Rc::new(SolidColor::from(Color::from(0.5, 0.1, 0.4)));
+    fabric.roughness = Rc::new(SolidColor::scalar(1.0));
+    fabric.specular = Rc::new(SolidColor::scalar(0.0));
+    fabric.sheen = Rc::new(SolidColor::scalar(1.0));
+
+    // Slightly frosted glass
+    let mut glass = Principled::new();
+    glass.base_color = Rc::new(SolidColor::from(Color::from(1.0, 1.0, 1.0)));
+    glass.roughness = Rc::new(SolidColor::scalar(0.1));
+    glass.transmission = Rc::new(SolidColor::scalar(1.0));
+    glass.specular_tint = Rc::new(SolidColor::scalar(0.0));
+
+    let materials = [plastic, brushed, paint, fabric, glass];
+    for (i, material) in materials.into_iter().enumerate() {
+        world.add(Box::new(Sphere::from(
+            Point3::from(-4.4 + 2.2 * i as f64, 1.0, 0.0),
+            1.0,
+            Rc::new(material),
+        )));
+    }
+
+    let mut cam = Camera::new();
+    cam.aspect_ratio = 16.0 / 9.0;
+    cam.image_width = 600;
+    cam.samples_per_pixel = 200;
+
+    cam.vfov = 40.0;
+    cam.lookfrom = Point3::from(0.0, 3.0, 12.0);
+    cam.lookat = Point3::from(0.0, 1.0, 0.0);
+    cam.vup = Vec3::from(0.0, 1.0, 0.0);
+
+    let mut integrator = SimplePathTracer::new();
+    integrator.max_depth = 50;
+
+    cam.render(&world, &LightList::new(), &integrator);
+}
+
+pub fn simple_light() {
+    let mut world: HittableList = HittableList::new();
+    let mut lights = LightList::with_sampling(LightSampling::Uniform);
+
+    let ground_material = Rc::new(Lambertian::from(Color::from(0.5, 0.5, 0.5)));
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.0, -1000.0, 0.0),
+        1000.0,
+        ground_material,
+    )));
+
+    let red = Rc::new(Lambertian::from(Color::from(0.7, 0.2, 0.1)));
+    world.add(Box::new(Sphere::from(
+        Point3::from(-1.2, 1.0, 0.0),
+        1.0,
+        red,
+    )));
+
+    let gold = Rc::new(Conductor::gold(0.3));
+    world.add(Box::new(Sphere::from(
+        Point3::from(1.2, 1.0, 0.0),
+        1.0,
+        gold,
+    )));
+
+    // Small, bright emitters that are hard to hit by chance
+    let emitters = [
+        (
+            Point3::from(0.0, 3.5, 1.0),
+            0.15,
+            Color::from(60.0, 55.0, 45.0),
+        ),
+        (
+            Point3::from(-3.0, 0.4, 2.0),
+            0.1,
+            Color::from(20.0, 30.0, 60.0),
+        ),
+    ];
+    for (center, radius, emit) in emitters {
+        let emitter = Rc::new(DiffuseLight::from(emit));
+        world.add(Box::new(Sphere::from(center, radius, emitter)));
+        lights.add(Rc::new(SphereLight::from(center, radius, emit)));
+    }
+
+    let mut cam = Camera::new();
+    cam.aspect_ratio = 16.0 / 9.0;
+    cam.image_width = 600;
+    cam.samples_per_pixel = 100;
+    cam.background = Background::Solid(Color::from(0.01, 0.01, 0.02));
+
+    cam.vfov = 30.0;
+    cam.lookfrom = Point3::from(0.0, 2.0, 9.0);
+    cam.lookat = Point3::from(0.0, 1.0, 0.0);
+    cam.vup = Vec3::from(0.0, 1.0, 0.0);
+
+    let mut integrator = SimplePathTracer::new();
+    integrator.max_depth = 50;
+
+    cam.render(&world, &lights, &integrator);
+}
+
+pub fn lookdev() {
+    let mut world: HittableList = HittableList::new();
+    let mut lights = LightList::new();
+
+    let ground_material = Rc::new(Lambertian::from(Color::from(0.5, 0.5, 0.5)));
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.0, -1000.0, 0.0),
+        1000.0,
+        ground_material,
+    )));
+
+    let mut paint = Principled::new();
+    paint.base_color = Rc::new(SolidColor::from(Color::from(0.1, 0.3, 0.6)));
+    paint.roughness = Rc::new(SolidColor::scalar(0.3));
+    paint.clearcoat = Rc::new(SolidColor::scalar(1.0));
+    world.add(Box::new(Sphere::from(
+        Point3::from(-1.2, 1.0, 0.0),
+        1.0,
+        Rc::new(paint),
+    )));
+
+    let copper = Rc::new(Conductor::copper(0.25));
+    world.add(Box::new(Sphere::from(
+        Point3::from(1.2, 1.0, 0.0),
+        1.0,
+        copper,
+    )));
+
+    // Low warm sun, a blue key spot and a small fill point light
+    lights.add(Rc::new(DirectionalLight::from(
+        Vec3::from(-1.0, -0.6, -0.4),
+        Color::from(2.0, 1.7, 1.3),
+    )));
+    lights.add(Rc::new(SpotLight::from(
+        Point3::from(0.0, 5.0, 4.0),
+        Point3::from(0.0, 1.0, 0.0),
+        Color::from(20.0, 25.0, 40.0),
+        25.0,
+        15.0,
+    )));
+    lights.add(Rc::new(PointLight::from(
+        Point3::from(-3.0, 2.0, 3.0),
+        Color::from(4.0, 4.0, 4.0),
+    )));
+
+    let mut cam = Camera::new();
+    cam.aspect_ratio = 16.0 / 9.0;
+    cam.image_width = 600;
+    cam.samples_per_pixel = 50;
+    cam.background = Background::Solid(Color::from(0.05, 0.05, 0.08));
+
+    cam.vfov = 30.0;
+    cam.lookfrom = Point3::from(0.0, 2.0, 9.0);
+    cam.lookat = Point3::from(0.0, 1.0, 0.0);
+    cam.vup = Vec3::from(0.0, 1.0, 0.0);
+
+    let mut integrator = SimplePathTracer::new();
+    integrator.max_depth = 20;
+
+    cam.render(&world, &lights, &integrator);
+}
+
+pub fn product_shot() {
+    // Two finishes floating on nothing, lit by the sky and a sun, with a
+    // transparent background for placing over a web page
+    let mut world: HittableList = HittableList::new();
+    let mut lights = LightList::new();
+
+    let mut paint = Principled::new();
+    paint.base_color = Rc::new(SolidColor::from(Color::from(0.7, 0.1, 0.1)));
+    paint.roughness = Rc::new(SolidColor::scalar(0.3));
+    paint.clearcoat = Rc::new(SolidColor::scalar(1.0));
+    world.add(Box::new(Sphere::from(
+        Point3::from(-0.6, 0.0, 0.0),
+        0.5,
+        Rc::new(paint),
+    )));
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.6, 0.0, 0.0),
+        0.5,
+        Rc::new(Conductor::gold(0.2)),
+    )));
+
+    lights.add(Rc::new(DirectionalLight::from(
+        Vec3::from(-1.0, -1.0, -0.5),
+        Color::from(2.0, 1.9, 1.7),
+    )));
+
+    let mut cam = Camera::new();
+    cam.aspect_ratio = 16.0 / 9.0;
+    cam.image_width = 600;
+    cam.samples_per_pixel = 64;
+    cam.transparent_background = true;
+    cam.png_path = Some("product.png".to_string());
+    cam.exposure = -1.5;
+    cam.tone_map = ToneMap::Agx;
+
+    cam.vfov = 30.0;
+    cam.lookfrom = Point3::from(0.0, 0.5, 4.0);
+    cam.lookat = Point3::from(0.0, 0.0, 0.0);
+    cam.vup = Vec3::from(0.0, 1.0, 0.0);
+
+    let mut integrator = SimplePathTracer::new();
+    integrator.max_depth = 20;
+
+    cam.render(&world, &lights, &integrator);
+}
+
+pub fn night_city() {
+    let mut world: HittableList = HittableList::new();
+    let mut lights = LightList::with_sampling(LightSampling::Bvh);
+    let mut sampler = IndependentSampler::new();
+
+    let ground_material = Rc::new(Lambertian::from(Color::from(0.3, 0.3, 0.3)));
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.0, -1000.0, 0.0),
+        1000.0,
+        ground_material,
+    )));
+
+    // Rows of large diffuse "buildings" lined with small lamps of varying
+    // colour and brightness, most of which barely light the view.
+    let building = Rc::new(Lambertian::from(Color::from(0.4, 0.4, 0.45)));
+    for a in -10..10 {
+        for b in -20..0 {
+            let x = 4.0 * a as f64;
+            let z = 4.0 * b as f64;
+            world.add(Box::new(Sphere::from(
+                Point3::from(x, 0.0, z),
+                1.2,
+                building.clone(),
+            )));
+
+            for _ in 0..4 {
+                let center = Point3::from(
+                    x + 2.0 + utils::random_double_in(-0.3, 0.3, &mut sampler),
+                    utils::random_double_in(0.1, 0.6, &mut sampler),
+                    z + utils::random_double_in(-2.0, 2.0, &mut sampler),
+                );
+                let hue = Color::random_in(0.3, 1.0, &mut sampler);
+                let emit =
+                    utils::random_double_in(5.0, 60.0, &mut sampler) * hue;
+                let radius = 0.04;
+                let emitter = Rc::new(DiffuseLight::from(emit));
+                world.add(Box::new(Sphere::from(center, radius, emitter)));
+                lights.add(Rc::new(SphereLight::from(center, radius, emit)));
+            }
+        }
+    }
+
+    let mut cam = Camera::new();
+    cam.aspect_ratio = 16.0 / 9.0;
+    cam.image_width = 400;
+    cam.samples_per_pixel = 16;
+    cam.background = Background::Solid(Color::from(0.002, 0.002, 0.006));
+
+    cam.vfov = 40.0;
+    cam.lookfrom = Point3::from(1.0, 3.0, 6.0);
+    cam.lookat = Point3::from(0.0, 0.0, -12.0);
+    cam.vup = Vec3::from(0.0, 1.0, 0.0);
+
+    let mut integrator = SimplePathTracer::new();
+    integrator.max_depth = 8;
+
+    cam.render(&world, &lights, &integrator);
+}
+
+pub fn scene_check(view: &str) {
+    // Renders a small scene with a quick preview integrator: "ao", "normal",
+    // "depth", "uv", "material" or "front_face".
+    let integrator: Box<dyn Integrator> = match view {
+        "ao" => Box::new(AmbientOcclusion::new()),
+        "normal" => Box::new(DebugShading::from(DebugView::Normal)),
+        "depth" => Box::new(DebugShading::from(DebugView::Depth)),
+        "uv" => Box::new(DebugShading::from(DebugView::Uv)),
+        "material" => Box::new(DebugShading::from(DebugView::MaterialId)),
+        "front_face" => Box::new(DebugShading::from(DebugView::FrontFace)),
+        _ => panic!("unknown view: {}", view),
+    };
+
+    let mut world: HittableList = HittableList::new();
+
+    let checker = Rc::new(CheckerTexture::from(
+        0.5,
+        Rc::new(SolidColor::from(Color::from(0.2, 0.3, 0.1))),
+        Rc::new(SolidColor::from(Color::from(0.9, 0.9, 0.9))),
+    ));
+    let mut ground = Principled::new();
+    ground.base_color = checker;
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.0, -1000.0, 0.0),
+        1000.0,
+        Rc::new(ground),
+    )));
+
+    let diffuse = Rc::new(Lambertian::from(Color::from(0.7, 0.3, 0.3)));
+    world.add(Box::new(Sphere::from(
+        Point3::from(-2.2, 1.0, 0.0),
+        1.0,
+        diffuse,
+    )));
+    let glass = Rc::new(Dielectric::from(1.5));
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.0, 1.0, 0.0),
+        1.0,
+        glass,
+    )));
+    let metal = Rc::new(Metal::from(Color::from(0.8, 0.8, 0.8), 0.1));
+    world.add(Box::new(Sphere::from(
+        Point3::from(2.2, 1.0, 0.0),
+        1.0,
+        metal,
+    )));
+
+    let mut cam = Camera::new();
+    cam.aspect_ratio = 16.0 / 9.0;
+    cam.image_width = 400;
+    cam.samples_per_pixel = 16;
+
+    cam.vfov = 30.0;
+    cam.lookfrom = Point3::from(0.0, 2.0, 10.0);
+    cam.lookat = Point3::from(0.0, 1.0, 0.0);
+    cam.vup = Vec3::from(0.0, 1.0, 0.0);
+
+    cam.render(&world, &LightList::new(), integrator.as_ref());
+}
+
+fn caustics_scene() -> (HittableList, LightList, Camera) {
+    let mut world: HittableList = HittableList::new();
+    let mut lights = LightList::new();
+
+    let ground_material = Rc::new(Lambertian::from(Color::from(0.6, 0.6, 0.6)));
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.0, -1000.0, 0.0),
+        1000.0,
+        ground_material,
+    )));
+
+    // Glass ball focusing a small light into a caustic on the floor, next
+    // to a diffuse ball for reference
+    let glass = Rc::new(Dielectric::from(1.5));
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.0, 1.0, 0.0),
+        1.0,
+        glass,
+    )));
+    let diffuse = Rc::new(Lambertian::from(Color::from(0.2, 0.4, 0.7)));
+    world.add(Box::new(Sphere::from(
+        Point3::from(-2.5, 0.7, -0.5),
+        0.7,
+        diffuse,
+    )));
+
+    let center = Point3::from(-1.0, 5.0, -1.0);
+    let radius = 0.2;
+    let emit = Color::from(200.0, 190.0, 170.0);
+    world.add(Box::new(Sphere::from(
+        center,
+        radius,
+        Rc::new(DiffuseLight::from(emit)),
+    )));
+    lights.add(Rc::new(SphereLight::from(center, radius, emit)));
+
+    let mut cam = Camera::new();
+    cam.aspect_ratio = 16.0 / 9.0;
+    cam.image_width = 400;
+    cam.samples_per_pixel = 64;
+    cam.background = Background::Solid(Color::from(0.02, 0.02, 0.03));
+
+    cam.vfov = 35.0;
+    cam.lookfrom = Point3::from(2.0, 3.0, 8.0);
+    cam.lookat = Point3::from(-0.5, 0.5, 0.0);
+    cam.vup = Vec3::from(0.0, 1.0, 0.0);
+
+    (world, lights, cam)
+}
+
+pub fn caustics() {
+    let (world, lights, mut cam) = caustics_scene();
+
+    let mut integrator = Bdpt::new();
+    integrator.max_depth = 8;
+
+    cam.render(&world, &lights, &integrator);
+}
+
+pub fn caustics_passes() {
+    // `caustics`, with the render passes for compositing
+    let (world, lights, mut cam) = caustics_scene();
+    cam.aov_path = Some("caustics.exr".to_string());
+
+    let mut integrator = Bdpt::new();
+    integrator.max_depth = 8;
+
+    cam.render(&world, &lights, &integrator);
+}
+
+pub fn caustics_sppm() {
+    // `caustics` with photon mapping. Photons only leave the explicit
+    // lights, so the little light the dim background adds after a bounce
+    // is missing.
+    let (world, lights, mut cam) = caustics_scene();
+
+    let mut integrator = Sppm::new();
+    integrator.iterations = 64;
+    integrator.photons_per_iteration = 200_000;
+    integrator.initial_radius = 0.05;
+    integrator.max_depth = 8;
+
+    let film = integrator.render_film(&mut cam, &world, &lights);
+    film.write_ppm(1);
+    log::info!("Done");
+}
+
+pub fn hidden_light() {
+    let mut world: HittableList = HittableList::new();
+    let mut lights = LightList::new();
+
+    // A room lit only through the thin gap under a large ball, with the
+    // lamp tucked in underneath it
+    let white = Rc::new(Lambertian::from(Color::from(0.7, 0.7, 0.7)));
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.0, 0.0, 0.0),
+        12.0,
+        white.clone(),
+    )));
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.0, -1000.0, 0.0),
+        1000.0,
+        white,
+    )));
+    let occluder = Rc::new(Lambertian::from(Color::from(0.6, 0.3, 0.2)));
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.0, 1.06, 0.0),
+        1.0,
+        occluder,
+    )));
+    let diffuse = Rc::new(Lambertian::from(Color::from(0.2, 0.4, 0.7)));
+    world.add(Box::new(Sphere::from(
+        Point3::from(-2.5, 0.7, 1.0),
+        0.7,
+        diffuse,
+    )));
+
+    let center = Point3::from(0.0, 0.03, 0.0);
+    let radius = 0.02;
+    let emit = Color::from(40000.0, 38000.0, 34000.0);
+    world.add(Box::new(Sphere::from(
+        center,
+        radius,
+        Rc::new(DiffuseLight::from(emit)),
+    )));
+    lights.add(Rc::new(SphereLight::from(center, radius, emit)));
+
+    let mut cam = Camera::new();
+    cam.aspect_ratio = 16.0 / 9.0;
+    cam.image_width = 400;
+    cam.background = Background::Solid(Color::new());
+
+    cam.vfov = 50.0;
+    cam.lookfrom = Point3::from(3.0, 3.0, 8.0);
+    cam.lookat = Point3::from(-0.5, 0.5, 0.0);
+    cam.vup = Vec3::from(0.0, 1.0, 0.0);
+
+    let mut integrator = Mlt::new();
+    integrator.max_depth = 8;
+    integrator.mutations_per_pixel = 256;
+
+    let film = integrator.render_film(&mut cam, &world, &lights);
+    film.write_ppm(integrator.mutations_per_pixel);
+    log::info!("Done");
+}
+
+pub fn spectral() {
+    let mut world: HittableList = HittableList::new();
+    let mut lights = LightList::new();
+
+    let ground_material = Rc::new(Lambertian::from(Color::from(0.8, 0.8, 0.8)));
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.0, -1000.0, 0.0),
+        1000.0,
+        ground_material,
+    )));
+
+    // Dense flint glass spreads the light it focuses into a rainbow
+    let flint = Rc::new(Dielectric::dispersive(
+        Dispersion::Cauchy {
+            a: 1.7280,
+            b: 0.01342,
+        },
+        Color::new(),
+    ));
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.0, 1.0, 0.0),
+        1.0,
+        flint,
+    )));
+
+    let gold = Rc::new(Conductor::gold(0.1));
+    world.add(Box::new(Sphere::from(
+        Point3::from(-2.4, 0.8, -0.5),
+        0.8,
+        gold,
+    )));
+
+    // Soap bubble, colored by interference in its film
+    let bubble = Rc::new(ThinFilm::from(400.0, 1.33));
+    world.add(Box::new(Sphere::from(
+        Point3::from(2.3, 0.9, 0.0),
+        0.9,
+        bubble,
+    )));
+
+    let center = Point3::from(4.0, 6.0, -3.0);
+    let radius = 0.3;
+    let emit = Color::from(150.0, 150.0, 150.0);
+    world.add(Box::new(Sphere::from(
+        center,
+        radius,
+        Rc::new(DiffuseLight::from(emit)),
+    )));
+    lights.add(Rc::new(SphereLight::from(center, radius, emit)));
+
+    let mut cam = Camera::new();
+    cam.aspect_ratio = 16.0 / 9.0;
+    cam.image_width = 600;
+    cam.samples_per_pixel = 256;
+    cam.background = Background::Solid(Color::from(0.05, 0.05, 0.08));
+    cam.spectral = true;
+
+    cam.vfov = 35.0;
+    cam.lookfrom = Point3::from(0.0, 3.0, 9.0);
+    cam.lookat = Point3::from(0.0, 0.7, 0.0);
+    cam.vup = Vec3::from(0.0, 1.0, 0.0);
+
+    let mut integrator = Bdpt::new();
+    integrator.max_depth = 8;
+
+    cam.render(&world, &lights, &integrator);
+}
+
+pub fn sampling(pattern: &str) {
+    // Renders a scene with soft shadows, glossy reflections and depth of
+    // field at a low sample count, to compare sample patterns:
+    // "independent", "stratified", "halton" or "sobol".
+    let sampling = match pattern {
+        "independent" => Sampling::Independent,
+        "stratified" => Sampling::Stratified,
+        "halton" => Sampling::Halton,
+        "sobol" => Sampling::Sobol,
+        _ => panic!("unknown pattern: {}", pattern),
+    };
+
+    let mut world: HittableList = HittableList::new();
+    let mut lights = LightList::new();
+
+    let ground_material = Rc::new(Lambertian::from(Color::from(0.6, 0.6, 0.6)));
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.0, -1000.0, 0.0),
+        1000.0,
+        ground_material,
+    )));
+
+    let diffuse = Rc::new(Lambertian::from(Color::from(0.7, 0.3, 0.3)));
+    world.add(Box::new(Sphere::from(
+        Point3::from(-2.2, 1.0, -1.5),
+        1.0,
+        diffuse,
+    )));
+    let copper = Rc::new(Conductor::copper(0.2));
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.0, 1.0, 0.0),
+        1.0,
+        copper,
+    )));
+    let frosted =
+        Rc::new(RoughDielectric::from(1.5, 0.15, Color::from(1.0, 1.0, 1.0)));
+    world.add(Box::new(Sphere::from(
+        Point3::from(2.2, 1.0, 1.5),
+        1.0,
+        frosted,
+    )));
+
+    let center = Point3::from(-3.0, 5.0, 3.0);
+    let radius = 1.0;
+    let emit = Color::from(10.0, 10.0, 10.0);
+    world.add(Box::new(Sphere::from(
+        center,
+        radius,
+        Rc::new(DiffuseLight::from(emit)),
+    )));
+    lights.add(Rc::new(SphereLight::from(center, radius, emit)));
+
+    let mut cam = Camera::new();
+    cam.aspect_ratio = 16.0 / 9.0;
+    cam.image_width = 400;
+    cam.samples_per_pixel = 16;
+    cam.background = Background::Solid(Color::from(0.1, 0.1, 0.15));
+    cam.sampling = sampling;
+
+    cam.vfov = 30.0;
+    cam.lookfrom = Point3::from(0.0, 2.5, 10.0);
+    cam.lookat = Point3::from(0.0, 1.0, 0.0);
+    cam.vup = Vec3::from(0.0, 1.0, 0.0);
+    cam.defocus_angle = 1.0;
+    cam.focus_dist = 10.0;
+
+    cam.render(&world, &lights, &SimplePathTracer::new());
+}
+
+pub fn filters(name: &str) {
+    // Renders fine detail that aliases easily, a checkerboard running off
+    // to the horizon and thin wires of tiny beads, at a low sample count to
+    // compare reconstruction filters: "box", "tent", "gaussian", "mitchell"
+    // or "lanczos".
+    let filter = match name {
+        "box" => Filter::Box { radius: 0.5 },
+        "tent" => Filter::Tent { radius: 1.0 },
+        "gaussian" => Filter::gaussian(),
+        "mitchell" => Filter::mitchell(),
+        "lanczos" => Filter::lanczos(),
+        _ => panic!("unknown filter: {}", name),
+    };
+
+    let mut world: HittableList = HittableList::new();
+
+    let checker = Rc::new(CheckerTexture::from(
+        0.5,
+        Rc::new(SolidColor::from(Color::from(0.05, 0.05, 0.05))),
+        Rc::new(SolidColor::from(Color::from(0.9, 0.9, 0.9))),
+    ));
+    let mut ground_material = Principled::new();
+    ground_material.base_color = checker;
+    ground_material.roughness = Rc::new(SolidColor::scalar(0.8));
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.0, -1000.0, 0.0),
+        1000.0,
+        Rc::new(ground_material),
+    )));
+
+    // Wires strung across the view at different heights and slants
+    let wire = Rc::new(Conductor::aluminum(0.1));
+    for k in 0..5 {
+        let height = 0.6 + 0.35 * k as f64;
+        let slant = 0.08 * k as f64;
+        for n in -200..=200 {
+            let x = n as f64 * 0.015;
+            world.add(Box::new(Sphere::from(
+                Point3::from(x, height + slant * x, -1.0 + 0.3 * k as f64),
+                0.01,
+                wire.clone(),
+            )));
+        }
+    }
+
+    let mut cam = Camera::new();
+    cam.aspect_ratio = 16.0 / 9.0;
+    cam.image_width = 400;
+    cam.samples_per_pixel = 8;
+    cam.filter = filter;
+
+    cam.vfov = 40.0;
+    cam.lookfrom = Point3::from(0.0, 1.2, 5.0);
+    cam.lookat = Point3::from(0.0, 1.0, 0.0);
+    cam.vup = Vec3::from(0.0, 1.0, 0.0);
+
+    cam.render(&world, &LightList::new(), &SimplePathTracer::new());
+}
+
+pub fn tone_mapping(name: &str) {
+    // Renders a high contrast scene, saturated colors under a glaring
+    // light, to compare tone mapping operators: "clamp", "reinhard",
+    // "extended-reinhard", "aces" or "agx".
+    let tone_map = match name {
+        "clamp" => ToneMap::Clamp,
+        "reinhard" => ToneMap::Reinhard,
+        "extended-reinhard" => ToneMap::ExtendedReinhard { white: 16.0 },
+        "aces" => ToneMap::Aces,
+        "agx" => ToneMap::Agx,
+        _ => panic!("unknown tone mapping: {}", name),
+    };
+
+    let mut world: HittableList = HittableList::new();
+    let mut lights = LightList::new();
+
+    let ground_material = Rc::new(Lambertian::from(Color::from(0.5, 0.5, 0.5)));
+    world.add(Box::new(Sphere::from(
+        Point3::from(0.0, -1000.0, 0.0),
+        1000.0,
+        ground_material,
+    )));
+
+    let albedos = [
+        Color::from(0.8, 0.05, 0.05),
+        Color::from(0.05, 0.8, 0.05),
+        Color::from(0.05, 0.05, 0.8),
+    ];
+    for (k, albedo) in albedos.into_iter().enumerate() {
+        world.add(Box::new(Sphere::from(
+            Point3::from(-2.2 + 2.2 * k as f64, 1.0, 0.0),
+            1.0,
+            Rc::new(Lambertian::from(albedo)),
+        )));
+    }
+
+    let center = Point3::from(0.0, 4.0, 2.0);
+    let radius = 0.5;
+    let emit = Color::from(80.0, 70.0, 50.0);
+    world.add(Box::new(Sphere::from(
+        center,
+        radius,
+        Rc::new(DiffuseLight::from(emit)),
+    )));
+    lights.add(Rc::new(SphereLight::from(center, radius, emit)));
+
+    let mut cam = Camera::new();
+    cam.aspect_ratio = 16.0 / 9.0;
+    cam.image_width = 400;
+    cam.samples_per_pixel = 64;
+    cam.background = Background::Solid(Color::from(0.02, 0.02, 0.03));
+    cam.exposure = 1.0;
+    cam.tone_map = tone_map;
+
+    cam.vfov = 40.0;
+    cam.lookfrom = Point3::from(0.0, 2.5, 9.0);
+    cam.lookat = Point3::from(0.0, 1.5, 0.0);
+    cam.vup = Vec3::from(0.0, 1.0, 0.0);
+
+    cam.render(&world, &lights, &SimplePathTracer::new());
 }
@@ -0,0 +1,631 @@
+use crate::light_bvh::{Aabb, LightBounds, LightBvh};
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::utils;
+use crate::vec3;
+use crate::vec3::{Color, Point3, Vec3};
+
+use std::cell::OnceCell;
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+pub struct LightSample {
+    // Unit direction from the shading point towards the light
+    pub wi: Vec3,
+    // Radiance arriving at the shading point along `wi`
+    pub radiance: Color,
+    // Solid angle density of `wi`
+    pub pdf: f64,
+    // Distance to the sampled point, used to bound the shadow ray
+    pub distance: f64,
+    // True for lights that can only be reached by explicit sampling
+    pub is_delta: bool,
+    // Surface normal at the sampled point, or zero for lights without a
+    // surface
+    pub n: Vec3,
+}
+
+pub struct LightEmission {
+    // Ray leaving the light, with a unit direction
+    pub ray: Ray,
+    // Surface normal at the ray origin, or zero for lights without a surface
+    pub n: Vec3,
+    // Radiance (or intensity, for point lights) carried by the ray
+    pub radiance: Color,
+    // Area density of the origin and solid angle density of the direction
+    pub pdf_pos: f64,
+    pub pdf_dir: f64,
+    pub is_delta: bool,
+}
+
+pub trait Light {
+    // Samples a direction from `p` towards the light.
+    fn sample_li(
+        &self,
+        p: &Point3,
+        sampler: &mut dyn Sampler,
+    ) -> Option<LightSample>;
+
+    // Solid angle density with which `sample_li` produces `wi` from `p`.
+    fn pdf_li(&self, p: &Point3, wi: &Vec3) -> f64;
+
+    // Spatial and directional bounds on the emitted power, used to choose
+    // between lights. None for infinitely distant lights.
+    fn bounds(&self) -> Option<LightBounds>;
+
+    // Samples a ray leaving the light, for tracing paths that start at the
+    // light. None for lights that can't be sampled this way.
+    fn sample_le(&self, sampler: &mut dyn Sampler) -> Option<LightEmission>;
+
+    // Densities with which `sample_le` leaves from `p` in direction `w`, as
+    // (area, solid angle). None if `p` isn't on the light.
+    fn pdf_le(&self, p: &Point3, w: &Vec3) -> Option<(f64, f64)>;
+}
+
+// Spherical area light emitting `emit` uniformly from its surface. It only
+// describes how to sample the light; the emitting geometry is added to the
+// world separately with a `DiffuseLight` material.
+pub struct SphereLight {
+    center: Point3,
+    radius: f64,
+    emit: Color,
+}
+
+impl SphereLight {
+    pub fn from(center: Point3, radius: f64, emit: Color) -> Self {
+        Self {
+            center,
+            radius,
+            emit,
+        }
+    }
+
+    fn one_minus_cos_theta_max(&self, p: &Point3) -> Option<f64> {
+        // Solid angle subtended by the sphere is 2π(1 - cos θmax). Points
+        // inside the sphere can't sample it this way.
+        let dist2 = (self.center - *p).length_squared();
+        let sin2_theta_max = self.radius * self.radius / dist2;
+        if sin2_theta_max >= 1.0 {
+            return None;
+        }
+
+        // Avoid catastrophic cancellation for small, distant spheres
+        if sin2_theta_max < 0.00068523 {
+            return Some(sin2_theta_max / 2.0);
+        }
+        Some(1.0 - (1.0 - sin2_theta_max).sqrt())
+    }
+}
+
+impl Light for SphereLight {
+    fn sample_li(
+        &self,
+        p: &Point3,
+        sampler: &mut dyn Sampler,
+    ) -> Option<LightSample> {
+        let one_minus_cos_max = self.one_minus_cos_theta_max(p)?;
+
+        // Uniformly sample the cone of directions subtended by the sphere
+        let to_center = self.center - *p;
+        let dist = to_center.length();
+        let (u1, u2) = sampler.get_2d();
+        let cos_theta = 1.0 - u1 * one_minus_cos_max;
+        let sin2_theta = 1.0 - cos_theta * cos_theta;
+        let phi = 2.0 * PI * u2;
+
+        let uvw = Onb::build_from_w(&to_center);
+        let sin_theta = sin2_theta.max(0.0).sqrt();
+        let wi = uvw.local(&Vec3::from(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ));
+
+        // Distance to the near intersection with the sphere
+        let disc = self.radius * self.radius - dist * dist * sin2_theta;
+        let distance = dist * cos_theta - disc.max(0.0).sqrt();
+
+        Some(LightSample {
+            wi,
+            radiance: self.emit,
+            pdf: 1.0 / (2.0 * PI * one_minus_cos_max),
+            distance,
+            is_delta: false,
+            n: vec3::unit_vector(*p + distance * wi - self.center),
+        })
+    }
+
+    fn pdf_li(&self, p: &Point3, wi: &Vec3) -> f64 {
+        let one_minus_cos_max = match self.one_minus_cos_theta_max(p) {
+            Some(x) => x,
+            None => return 0.0,
+        };
+
+        // Only directions inside the cone reach the sphere
+        let to_center = self.center - *p;
+        let cos_theta =
+            vec3::dot(&vec3::unit_vector(*wi), &vec3::unit_vector(to_center));
+        if cos_theta < 1.0 - one_minus_cos_max {
+            return 0.0;
+        }
+        1.0 / (2.0 * PI * one_minus_cos_max)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        // Emits over the whole sphere of directions
+        let r = Vec3::from(self.radius, self.radius, self.radius);
+        let area = 4.0 * PI * self.radius * self.radius;
+        Some(LightBounds {
+            bounds: Aabb::from(self.center - r, self.center + r),
+            w: Vec3::from(0.0, 0.0, 1.0),
+            phi: PI * area * vec3::luminance(&self.emit),
+            cos_theta_o: -1.0,
+            cos_theta_e: 0.0,
+        })
+    }
+
+    fn sample_le(&self, sampler: &mut dyn Sampler) -> Option<LightEmission> {
+        // Uniform point on the sphere, cosine-weighted direction around the
+        // normal there
+        let n = vec3::random_unit_vector(sampler);
+        let origin = self.center + self.radius * n;
+        let local = vec3::random_cosine_direction(sampler);
+        let direction = Onb::build_from_w(&n).local(&local);
+        Some(LightEmission {
+            ray: Ray::from(origin, direction),
+            n,
+            radiance: self.emit,
+            pdf_pos: 1.0 / (4.0 * PI * self.radius * self.radius),
+            pdf_dir: local.z() / PI,
+            is_delta: false,
+        })
+    }
+
+    fn pdf_le(&self, p: &Point3, w: &Vec3) -> Option<(f64, f64)> {
+        let offset = *p - self.center;
+        if (offset.length() - self.radius).abs() > 1e-6 * self.radius.max(1.0) {
+            return None;
+        }
+        let cos_theta =
+            vec3::dot(&vec3::unit_vector(offset), &vec3::unit_vector(*w));
+        Some((
+            1.0 / (4.0 * PI * self.radius * self.radius),
+            cos_theta.max(0.0) / PI,
+        ))
+    }
+}
+
+// Isotropic point light with radiant intensity `intensity`.
+pub struct PointLight {
+    position: Point3,
+    intensity: Color,
+}
+
+impl PointLight {
+    pub fn from(position: Point3, intensity: Color) -> Self {
+        Self {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample_li(
+        &self,
+        p: &Point3,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<LightSample> {
+        let to_light = self.position - *p;
+        let dist2 = to_light.length_squared();
+        Some(LightSample {
+            wi: vec3::unit_vector(to_light),
+            radiance: self.intensity / dist2,
+            pdf: 1.0,
+            distance: dist2.sqrt(),
+            is_delta: true,
+            n: Vec3::new(),
+        })
+    }
+
+    fn pdf_li(&self, _p: &Point3, _wi: &Vec3) -> f64 {
+        0.0
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds {
+            bounds: Aabb::from(self.position, self.position),
+            w: Vec3::from(0.0, 0.0, 1.0),
+            phi: 4.0 * PI * vec3::luminance(&self.intensity),
+            cos_theta_o: -1.0,
+            cos_theta_e: 0.0,
+        })
+    }
+
+    fn sample_le(&self, sampler: &mut dyn Sampler) -> Option<LightEmission> {
+        Some(LightEmission {
+            ray: Ray::from(self.position, vec3::random_unit_vector(sampler)),
+            n: Vec3::new(),
+            radiance: self.intensity,
+            pdf_pos: 1.0,
+            pdf_dir: 1.0 / (4.0 * PI),
+            is_delta: true,
+        })
+    }
+
+    fn pdf_le(&self, p: &Point3, _w: &Vec3) -> Option<(f64, f64)> {
+        if !(*p - self.position).near_zero() {
+            return None;
+        }
+        Some((1.0, 1.0 / (4.0 * PI)))
+    }
+}
+
+// Point light restricted to a cone around the direction it points at, with a
+// smooth falloff between `falloff_start` and `total_width` (both in degrees
+// from the axis).
+pub struct SpotLight {
+    position: Point3,
+    axis: Vec3,
+    intensity: Color,
+    cos_falloff_start: f64,
+    cos_total_width: f64,
+}
+
+impl SpotLight {
+    pub fn from(
+        position: Point3,
+        lookat: Point3,
+        intensity: Color,
+        total_width: f64,
+        falloff_start: f64,
+    ) -> Self {
+        Self {
+            position,
+            axis: vec3::unit_vector(lookat - position),
+            intensity,
+            cos_falloff_start: utils::degrees_to_radians(falloff_start).cos(),
+            cos_total_width: utils::degrees_to_radians(total_width).cos(),
+        }
+    }
+
+    fn falloff(&self, cos_theta: f64) -> f64 {
+        if cos_theta <= self.cos_total_width {
+            return 0.0;
+        }
+        if cos_theta >= self.cos_falloff_start {
+            return 1.0;
+        }
+        // Smoothstep between the two cone edges
+        let t = (cos_theta - self.cos_total_width)
+            / (self.cos_falloff_start - self.cos_total_width);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample_li(
+        &self,
+        p: &Point3,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<LightSample> {
+        let to_light = self.position - *p;
+        let dist2 = to_light.length_squared();
+        let wi = vec3::unit_vector(to_light);
+        let falloff = self.falloff(vec3::dot(&-wi, &self.axis));
+        if falloff <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            radiance: (falloff / dist2) * self.intensity,
+            pdf: 1.0,
+            distance: dist2.sqrt(),
+            is_delta: true,
+            n: Vec3::new(),
+        })
+    }
+
+    fn pdf_li(&self, _p: &Point3, _wi: &Vec3) -> f64 {
+        0.0
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        // Full intensity inside the falloff cone, spreading out to the total
+        // width. The power is an upper bound, as if emitting in every
+        // direction.
+        let theta_e =
+            self.cos_total_width.acos() - self.cos_falloff_start.acos();
+        Some(LightBounds {
+            bounds: Aabb::from(self.position, self.position),
+            w: self.axis,
+            phi: 4.0 * PI * vec3::luminance(&self.intensity),
+            cos_theta_o: self.cos_falloff_start,
+            cos_theta_e: theta_e.cos(),
+        })
+    }
+
+    fn sample_le(&self, sampler: &mut dyn Sampler) -> Option<LightEmission> {
+        // Uniform direction inside the cone of total width
+        let (u1, u2) = sampler.get_2d();
+        let cos_theta = 1.0 - u1 * (1.0 - self.cos_total_width);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u2;
+        let direction = Onb::build_from_w(&self.axis).local(&Vec3::from(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ));
+        Some(LightEmission {
+            ray: Ray::from(self.position, direction),
+            n: Vec3::new(),
+            radiance: self.falloff(cos_theta) * self.intensity,
+            pdf_pos: 1.0,
+            pdf_dir: 1.0 / (2.0 * PI * (1.0 - self.cos_total_width)),
+            is_delta: true,
+        })
+    }
+
+    fn pdf_le(&self, p: &Point3, w: &Vec3) -> Option<(f64, f64)> {
+        if !(*p - self.position).near_zero() {
+            return None;
+        }
+        let cos_theta = vec3::dot(&self.axis, &vec3::unit_vector(*w));
+        if cos_theta < self.cos_total_width {
+            return Some((1.0, 0.0));
+        }
+        Some((1.0, 1.0 / (2.0 * PI * (1.0 - self.cos_total_width))))
+    }
+}
+
+// Infinitely distant light, such as the sun, arriving from a single
+// direction. `direction` is the direction the light travels in.
+pub struct DirectionalLight {
+    direction: Vec3,
+    radiance: Color,
+}
+
+impl DirectionalLight {
+    pub fn from(direction: Vec3, radiance: Color) -> Self {
+        Self {
+            direction: vec3::unit_vector(direction),
+            radiance,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample_li(
+        &self,
+        _p: &Point3,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<LightSample> {
+        Some(LightSample {
+            wi: -self.direction,
+            radiance: self.radiance,
+            pdf: 1.0,
+            distance: f64::INFINITY,
+            is_delta: true,
+            n: Vec3::new(),
+        })
+    }
+
+    fn pdf_li(&self, _p: &Point3, _wi: &Vec3) -> f64 {
+        0.0
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+
+    fn sample_le(&self, _sampler: &mut dyn Sampler) -> Option<LightEmission> {
+        // Would need the extent of the scene to pick a starting point
+        None
+    }
+
+    fn pdf_le(&self, _p: &Point3, _w: &Vec3) -> Option<(f64, f64)> {
+        None
+    }
+}
+
+// How `LightList` chooses which light to sample at a shading point.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightSampling {
+    // Every light is equally likely
+    Uniform,
+    // Proportional to each light's emitted power
+    Power,
+    // Proportional to each light's estimated contribution at the shading
+    // point, using a light bounding volume hierarchy
+    Bvh,
+}
+
+// Distribution over the lights, built on first use.
+enum Distribution {
+    Uniform,
+    Power,
+    Bvh(LightBvh),
+}
+
+pub struct LightList {
+    lights: Vec<Rc<dyn Light>>,
+    sampling: LightSampling,
+    // Indices of the infinite lights, which have no bounds and are chosen
+    // uniformly, alongside the finite lights as a group.
+    infinite: Vec<usize>,
+    distribution: OnceCell<Distribution>,
+    // Cumulative power of the finite lights, by light index
+    power: OnceCell<Vec<f64>>,
+}
+
+impl LightList {
+    pub fn new() -> Self {
+        Self::with_sampling(LightSampling::Power)
+    }
+
+    pub fn with_sampling(sampling: LightSampling) -> Self {
+        Self {
+            lights: Vec::new(),
+            sampling,
+            infinite: Vec::new(),
+            distribution: OnceCell::new(),
+            power: OnceCell::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    pub fn add(&mut self, light: Rc<dyn Light>) {
+        if light.bounds().is_none() {
+            self.infinite.push(self.lights.len());
+        }
+        self.lights.push(light);
+        self.distribution = OnceCell::new();
+        self.power = OnceCell::new();
+    }
+
+    fn distribution(&self) -> &Distribution {
+        self.distribution.get_or_init(|| match self.sampling {
+            LightSampling::Uniform => Distribution::Uniform,
+            LightSampling::Power => Distribution::Power,
+            LightSampling::Bvh => {
+                let bounds: Vec<(usize, LightBounds)> = self
+                    .lights
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, light)| light.bounds().map(|b| (i, b)))
+                    .collect();
+                Distribution::Bvh(LightBvh::from(&bounds, self.lights.len()))
+            }
+        })
+    }
+
+    fn power_cdf(&self) -> &[f64] {
+        self.power.get_or_init(|| {
+            // Infinite lights contribute nothing to the running sum, so they
+            // can never be picked from it.
+            let mut total = 0.0;
+            self.lights
+                .iter()
+                .map(|light| {
+                    total += light.bounds().map_or(0.0, |b| b.phi.max(0.0));
+                    total
+                })
+                .collect()
+        })
+    }
+
+    fn infinite_probability(&self) -> f64 {
+        // Chance of choosing among the infinite lights rather than the
+        // finite ones, treating all finite lights as one extra light.
+        let n_infinite = self.infinite.len();
+        if n_infinite == self.lights.len() {
+            return 1.0;
+        }
+        n_infinite as f64 / (n_infinite + 1) as f64
+    }
+
+    pub fn sample(&self, p: &Point3, u: f64) -> Option<(&dyn Light, f64)> {
+        // Picks a light for shading point `p`, returning it with its
+        // selection probability.
+        if self.lights.is_empty() {
+            return None;
+        }
+
+        let (index, pmf) = match self.distribution() {
+            Distribution::Uniform => {
+                let n = self.lights.len();
+                let index = ((u * n as f64) as usize).min(n - 1);
+                (index, 1.0 / n as f64)
+            }
+            distribution => {
+                let p_infinite = self.infinite_probability();
+                if u < p_infinite {
+                    let n = self.infinite.len();
+                    let i = ((u / p_infinite * n as f64) as usize).min(n - 1);
+                    (self.infinite[i], p_infinite / n as f64)
+                } else {
+                    let u = (u - p_infinite) / (1.0 - p_infinite);
+                    let (index, pmf) = match distribution {
+                        Distribution::Power => sample_cdf(self.power_cdf(), u)?,
+                        Distribution::Bvh(bvh) => bvh.sample(p, u)?,
+                        Distribution::Uniform => unreachable!(),
+                    };
+                    (index, (1.0 - p_infinite) * pmf)
+                }
+            }
+        };
+        Some((self.lights[index].as_ref(), pmf))
+    }
+
+    fn pmf(&self, p: &Point3, index: usize) -> f64 {
+        // Probability that `sample` picks light `index` from `p`
+        let p_infinite = self.infinite_probability();
+        match self.distribution() {
+            Distribution::Uniform => 1.0 / self.lights.len() as f64,
+            _ if self.infinite.contains(&index) => {
+                p_infinite / self.infinite.len() as f64
+            }
+            Distribution::Power => {
+                (1.0 - p_infinite) * cdf_pmf(self.power_cdf(), index)
+            }
+            Distribution::Bvh(bvh) => (1.0 - p_infinite) * bvh.pmf(p, index),
+        }
+    }
+
+    pub fn sample_emitter(&self, u: f64) -> Option<(&dyn Light, f64)> {
+        // Picks a light to start a path from, proportionally to its power,
+        // returning it with its selection probability.
+        let (index, pmf) = sample_cdf(self.power_cdf(), u)?;
+        Some((self.lights[index].as_ref(), pmf))
+    }
+
+    pub fn pdf_le(&self, p: &Point3, w: &Vec3) -> (f64, f64) {
+        // Densities with which a path starting at a light picked by
+        // `sample_emitter` leaves from `p` in direction `w`, as (area, solid
+        // angle). The area density includes the selection probability.
+        let cdf = self.power_cdf();
+        for (i, light) in self.lights.iter().enumerate() {
+            if let Some((pdf_pos, pdf_dir)) = light.pdf_le(p, w) {
+                return (cdf_pmf(cdf, i) * pdf_pos, pdf_dir);
+            }
+        }
+        (0.0, 0.0)
+    }
+
+    pub fn pdf_li(&self, p: &Point3, wi: &Vec3, hit: &Point3) -> f64 {
+        // Density of `wi` when light sampling from `p`, including the light
+        // selection probability, for the light found at `hit` along it.
+        // Other lights in the same direction are behind or inside it, and
+        // don't count.
+        for (i, light) in self.lights.iter().enumerate() {
+            if light.pdf_le(hit, &-*wi).is_some() {
+                return self.pmf(p, i) * light.pdf_li(p, wi);
+            }
+        }
+        0.0
+    }
+}
+
+fn sample_cdf(cdf: &[f64], u: f64) -> Option<(usize, f64)> {
+    // Inverts a cumulative distribution, returning the chosen index and its
+    // probability.
+    let total = *cdf.last()?;
+    if total <= 0.0 {
+        return None;
+    }
+    let index = cdf.partition_point(|&c| c <= u * total).min(cdf.len() - 1);
+    Some((index, cdf_pmf(cdf, index)))
+}
+
+fn cdf_pmf(cdf: &[f64], index: usize) -> f64 {
+    let total = cdf[cdf.len() - 1];
+    if total <= 0.0 {
+        return 0.0;
+    }
+    let below = if index == 0 { 0.0 } else { cdf[index - 1] };
+    (cdf[index] - below) / total
+}
@@ -0,0 +1,339 @@
+use crate::vec3;
+use crate::vec3::{Point3, Vec3};
+
+use std::f64::consts::PI;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Aabb {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Aabb {
+    pub fn from(a: Point3, b: Point3) -> Self {
+        Self {
+            min: Point3::from(
+                a.x().min(b.x()),
+                a.y().min(b.y()),
+                a.z().min(b.z()),
+            ),
+            max: Point3::from(
+                a.x().max(b.x()),
+                a.y().max(b.y()),
+                a.z().max(b.z()),
+            ),
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self::from(
+            Point3::from(
+                self.min.x().min(other.min.x()),
+                self.min.y().min(other.min.y()),
+                self.min.z().min(other.min.z()),
+            ),
+            Point3::from(
+                self.max.x().max(other.max.x()),
+                self.max.y().max(other.max.y()),
+                self.max.z().max(other.max.z()),
+            ),
+        )
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn diagonal(&self) -> Vec3 {
+        self.max - self.min
+    }
+}
+
+// Bounds on the emission of a light or a cluster of lights, following
+// Conty and Kulla, "Importance Sampling of Many Lights with Adaptive Tree
+// Splitting" (2018). Light leaves from inside `bounds`, with total power
+// `phi`, along surface normals within `cos_theta_o` of `w` and then spreads
+// at most `cos_theta_e` further.
+#[derive(Debug, Copy, Clone)]
+pub struct LightBounds {
+    pub bounds: Aabb,
+    pub w: Vec3,
+    pub phi: f64,
+    pub cos_theta_o: f64,
+    pub cos_theta_e: f64,
+}
+
+impl LightBounds {
+    pub fn union(&self, other: &LightBounds) -> Self {
+        if self.phi == 0.0 {
+            return *other;
+        }
+        if other.phi == 0.0 {
+            return *self;
+        }
+
+        let (w, cos_theta_o) = cone_union(
+            (self.w, self.cos_theta_o),
+            (other.w, other.cos_theta_o),
+        );
+        Self {
+            bounds: self.bounds.union(&other.bounds),
+            w,
+            phi: self.phi + other.phi,
+            cos_theta_o,
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+        }
+    }
+
+    pub fn importance(&self, p: &Point3) -> f64 {
+        // Conservative estimate of the light reaching `p`
+        if self.phi == 0.0 {
+            return 0.0;
+        }
+
+        let pc = self.bounds.centroid();
+        let d2 = (*p - pc)
+            .length_squared()
+            .max(self.bounds.diagonal().length() / 2.0);
+
+        // Angle between the emission axis and the direction towards p
+        let wi = vec3::unit_vector(*p - pc);
+        let cos_theta_w = vec3::dot(&self.w, &wi);
+        let sin_theta_w = safe_sqrt(1.0 - cos_theta_w * cos_theta_w);
+
+        // Angle subtended by the bounds as seen from p
+        let radius = self.bounds.diagonal().length() / 2.0;
+        let cos_theta_b = if (*p - pc).length_squared() < radius * radius {
+            -1.0
+        } else {
+            safe_sqrt(1.0 - radius * radius / (*p - pc).length_squared())
+        };
+        let sin_theta_b = safe_sqrt(1.0 - cos_theta_b * cos_theta_b);
+
+        // θ' = max(0, θw - θo - θb)
+        let sin_theta_o = safe_sqrt(1.0 - self.cos_theta_o * self.cos_theta_o);
+        let cos_theta_x = cos_sub_clamped(
+            sin_theta_w,
+            cos_theta_w,
+            sin_theta_o,
+            self.cos_theta_o,
+        );
+        let sin_theta_x = sin_sub_clamped(
+            sin_theta_w,
+            cos_theta_w,
+            sin_theta_o,
+            self.cos_theta_o,
+        );
+        let cos_theta_p =
+            cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+        if cos_theta_p <= self.cos_theta_e {
+            return 0.0;
+        }
+
+        (self.phi * cos_theta_p / d2).max(0.0)
+    }
+}
+
+fn safe_sqrt(x: f64) -> f64 {
+    x.max(0.0).sqrt()
+}
+
+fn cos_sub_clamped(sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64) -> f64 {
+    // cos(max(0, a - b))
+    if cos_a > cos_b {
+        return 1.0;
+    }
+    cos_a * cos_b + sin_a * sin_b
+}
+
+fn sin_sub_clamped(sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64) -> f64 {
+    // sin(max(0, a - b))
+    if cos_a > cos_b {
+        return 0.0;
+    }
+    sin_a * cos_b - cos_a * sin_b
+}
+
+fn cone_union(a: (Vec3, f64), b: (Vec3, f64)) -> (Vec3, f64) {
+    // Smallest cone of directions containing both cones
+    let entire_sphere = (Vec3::from(0.0, 0.0, 1.0), -1.0);
+
+    let theta_a = a.1.clamp(-1.0, 1.0).acos();
+    let theta_b = b.1.clamp(-1.0, 1.0).acos();
+    let theta_d = vec3::dot(&a.0, &b.0).clamp(-1.0, 1.0).acos();
+
+    if (theta_d + theta_b).min(PI) <= theta_a {
+        return a;
+    }
+    if (theta_d + theta_a).min(PI) <= theta_b {
+        return b;
+    }
+
+    let theta_o = (theta_a + theta_d + theta_b) / 2.0;
+    if theta_o >= PI {
+        return entire_sphere;
+    }
+
+    // Rotate a's axis towards b's so it bisects the spread
+    let theta_r = theta_o - theta_a;
+    let axis = vec3::cross(&a.0, &b.0);
+    if axis.length_squared() == 0.0 {
+        return entire_sphere;
+    }
+    let w = rotate(&a.0, &vec3::unit_vector(axis), theta_r);
+    (w, theta_o.cos())
+}
+
+fn rotate(v: &Vec3, axis: &Vec3, theta: f64) -> Vec3 {
+    // Rodrigues' rotation formula
+    let (sin_theta, cos_theta) = theta.sin_cos();
+    cos_theta * *v
+        + sin_theta * vec3::cross(axis, v)
+        + (1.0 - cos_theta) * vec3::dot(axis, v) * *axis
+}
+
+enum Node {
+    Leaf(usize),
+    Interior(usize, usize),
+}
+
+// Binary tree over light bounds, sampled top-down by choosing each child
+// proportionally to its importance at the shading point.
+pub struct LightBvh {
+    nodes: Vec<(LightBounds, Node)>,
+    // Path from the root to each light's leaf, one bit per level with 1
+    // meaning the second child. Indexed by light index; the median split
+    // keeps the tree balanced, so 64 levels are plenty.
+    trails: Vec<Option<u64>>,
+}
+
+impl LightBvh {
+    pub fn from(lights: &[(usize, LightBounds)], light_count: usize) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::new(),
+            trails: vec![None; light_count],
+        };
+        let mut lights = lights.to_vec();
+        if !lights.is_empty() {
+            bvh.build(&mut lights, 0, 0);
+        }
+        bvh
+    }
+
+    fn build(
+        &mut self,
+        lights: &mut [(usize, LightBounds)],
+        trail: u64,
+        depth: u32,
+    ) -> usize {
+        if lights.len() == 1 {
+            let (index, bounds) = lights[0];
+            self.trails[index] = Some(trail);
+            self.nodes.push((bounds, Node::Leaf(index)));
+            return self.nodes.len() - 1;
+        }
+
+        // Median split along the longest axis of the centroids
+        let mut centroids = Aabb::from(
+            lights[0].1.bounds.centroid(),
+            lights[0].1.bounds.centroid(),
+        );
+        for (_, bounds) in lights.iter() {
+            let c = bounds.bounds.centroid();
+            centroids = centroids.union(&Aabb::from(c, c));
+        }
+        let extent = centroids.diagonal();
+        let axis = if extent.x() > extent.y() && extent.x() > extent.z() {
+            0
+        } else if extent.y() > extent.z() {
+            1
+        } else {
+            2
+        };
+        lights.sort_by(|a, b| {
+            let ca = a.1.bounds.centroid().e[axis];
+            let cb = b.1.bounds.centroid().e[axis];
+            ca.total_cmp(&cb)
+        });
+
+        let mid = lights.len() / 2;
+        let node = self.nodes.len();
+        self.nodes.push((lights[0].1, Node::Leaf(lights[0].0)));
+
+        let (left, right) = lights.split_at_mut(mid);
+        let first = self.build(left, trail, depth + 1);
+        let second = self.build(right, trail | (1 << depth), depth + 1);
+
+        let bounds = self.nodes[first].0.union(&self.nodes[second].0);
+        self.nodes[node] = (bounds, Node::Interior(first, second));
+        node
+    }
+
+    pub fn sample(&self, p: &Point3, u: f64) -> Option<(usize, f64)> {
+        // Returns the index of the chosen light and its probability
+        if self.nodes.is_empty() || self.nodes[0].0.importance(p) == 0.0 {
+            return None;
+        }
+
+        let mut u = u;
+        let mut pmf = 1.0;
+        let mut node = 0;
+        loop {
+            match self.nodes[node].1 {
+                Node::Leaf(index) => return Some((index, pmf)),
+                Node::Interior(first, second) => {
+                    let ci = [
+                        self.nodes[first].0.importance(p),
+                        self.nodes[second].0.importance(p),
+                    ];
+                    if ci[0] == 0.0 && ci[1] == 0.0 {
+                        return None;
+                    }
+                    let p_first = ci[0] / (ci[0] + ci[1]);
+                    if u < p_first {
+                        node = first;
+                        pmf *= p_first;
+                        u = (u / p_first).min(1.0 - f64::EPSILON);
+                    } else {
+                        node = second;
+                        pmf *= 1.0 - p_first;
+                        u = ((u - p_first) / (1.0 - p_first))
+                            .min(1.0 - f64::EPSILON);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn pmf(&self, p: &Point3, index: usize) -> f64 {
+        // Probability that `sample` picks light `index` from `p`
+        let mut trail = match self.trails.get(index) {
+            Some(Some(trail)) => *trail,
+            _ => return 0.0,
+        };
+        if self.nodes[0].0.importance(p) == 0.0 {
+            return 0.0;
+        }
+
+        let mut pmf = 1.0;
+        let mut node = 0;
+        loop {
+            match self.nodes[node].1 {
+                Node::Leaf(_) => return pmf,
+                Node::Interior(first, second) => {
+                    let ci = [
+                        self.nodes[first].0.importance(p),
+                        self.nodes[second].0.importance(p),
+                    ];
+                    if ci[0] == 0.0 && ci[1] == 0.0 {
+                        return 0.0;
+                    }
+                    let next = (trail & 1) as usize;
+                    pmf *= ci[next] / (ci[0] + ci[1]);
+                    node = if next == 0 { first } else { second };
+                    trail >>= 1;
+                }
+            }
+        }
+    }
+}
@@ -1,17 +1,60 @@
 use crate::hittable::HitRecord;
+use crate::microfacet::{self, TrowbridgeReitz};
+use crate::onb::Onb;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::spectrum;
 use crate::utils;
 use crate::vec3;
 use crate::vec3::{Color, Vec3};
 
+use std::f64::consts::PI;
+
+pub struct ScatterRecord {
+    pub scattered: Ray,
+    // Estimator weight of the sampled direction, BSDF * |cos| / pdf
+    pub attenuation: Color,
+    // Solid angle density of the sampled direction, zero for delta lobes
+    pub pdf: f64,
+    pub is_specular: bool,
+}
+
 pub trait Material {
-    fn scatter(
+    // Samples a scattered direction for `r_in` hitting the surface at `rec`.
+    fn sample(
         &self,
         r_in: &Ray,
         rec: &HitRecord,
-        attenuation: &mut Vec3,
-        scattered: &mut Ray,
-    ) -> bool;
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord>;
+
+    // BSDF value for light arriving from direction `wi` and leaving towards
+    // the origin of `r_in`, times the cosine between `wi` and the shading
+    // normal. Delta lobes can't be evaluated and contribute nothing.
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _wi: &Vec3) -> Color {
+        Color::new()
+    }
+
+    // Solid angle density with which `sample` produces direction `wi`.
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _wi: &Vec3) -> f64 {
+        0.0
+    }
+
+    // True if the material only scatters through delta lobes.
+    fn is_specular(&self) -> bool {
+        false
+    }
+
+    // Radiance emitted by the surface towards the origin of `r_in`.
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord) -> Color {
+        Color::new()
+    }
+
+    // Overall color of the surface in RGB, for guiding the denoiser. Ones
+    // without a meaningful color, such as clear glass, are white.
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        Color::from(1.0, 1.0, 1.0)
+    }
 }
 
 pub struct Lambertian {
@@ -25,23 +68,69 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(
+    fn sample(
         &self,
         r_in: &Ray,
         rec: &HitRecord,
-        attenuation: &mut Vec3,
-        scattered: &mut Ray,
-    ) -> bool {
-        let mut scatter_direction = rec.normal + vec3::random_unit_vector();
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
+        let mut scatter_direction =
+            rec.normal + vec3::random_unit_vector(sampler);
 
         // Catch degenerate scatter direction
         if scatter_direction.near_zero() {
             scatter_direction = rec.normal;
         }
 
-        *scattered = Ray::from(rec.p, scatter_direction);
-        *attenuation = self.albedo;
-        return true;
+        let scattered = r_in.spawn(rec.p, scatter_direction);
+        Some(ScatterRecord {
+            scattered,
+            attenuation: spectrum::reflectance(&self.albedo, r_in),
+            pdf: self.pdf(r_in, rec, &scatter_direction),
+            is_specular: false,
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Color {
+        self.pdf(r_in, rec, wi) * spectrum::reflectance(&self.albedo, r_in)
+    }
+
+    fn pdf(&self, _r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> f64 {
+        let cosine = vec3::dot(&vec3::unit_vector(*wi), &rec.normal);
+        cosine.max(0.0) / PI
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+}
+
+// Emits `emit` from the front face of the surface and absorbs all light.
+pub struct DiffuseLight {
+    emit: Color,
+}
+
+impl DiffuseLight {
+    pub fn from(emit: Color) -> Self {
+        Self { emit }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn sample(
+        &self,
+        _r_in: &Ray,
+        _rec: &HitRecord,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
+        None
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        if !rec.front_face {
+            return Color::new();
+        }
+        spectrum::illuminant(&self.emit, r_in)
     }
 }
 
@@ -59,29 +148,318 @@ impl Metal {
     }
 }
 
+impl Metal {
+    fn fuzz_pdf(&self, reflected: &Vec3, wi: &Vec3) -> f64 {
+        // Density of directions towards a uniformly sampled point on the
+        // sphere of radius `fuzz` around the tip of the unit `reflected`
+        // vector. Both intersections of the direction with that sphere
+        // contribute, hence the sum of the squared distances t1 and t2.
+        let cos_theta = vec3::dot(&vec3::unit_vector(*wi), reflected);
+        let sin2_theta = 1.0 - cos_theta * cos_theta;
+        let fuzz2 = self.fuzz * self.fuzz;
+        if cos_theta <= 0.0 || sin2_theta >= fuzz2 {
+            return 0.0;
+        }
+        let t2_sum = 4.0 * cos_theta * cos_theta - 2.0 * (1.0 - fuzz2);
+        t2_sum / (4.0 * PI * self.fuzz * (fuzz2 - sin2_theta).sqrt())
+    }
+}
+
 impl Material for Metal {
-    fn scatter(
+    fn sample(
         &self,
         r_in: &Ray,
         rec: &HitRecord,
-        attenuation: &mut Vec3,
-        scattered: &mut Ray,
-    ) -> bool {
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
         let reflected =
             vec3::reflect(&vec3::unit_vector(r_in.direction()), &rec.normal);
-        *scattered = Ray::from(rec.p, reflected);
-        *attenuation = self.albedo;
-        return vec3::dot(&scattered.direction(), &rec.normal) > 0.0;
+        let scattered = r_in.spawn(
+            rec.p,
+            reflected + self.fuzz * vec3::random_unit_vector(sampler),
+        );
+        if vec3::dot(&scattered.direction(), &rec.normal) <= 0.0 {
+            return None;
+        }
+        Some(ScatterRecord {
+            scattered,
+            attenuation: spectrum::reflectance(&self.albedo, r_in),
+            pdf: self.pdf(r_in, rec, &scattered.direction()),
+            is_specular: self.is_specular(),
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Color {
+        // The fuzz lobe is defined by how it's sampled: every direction it
+        // produces carries the albedo, and those below the surface are
+        // absorbed. Its BSDF times the cosine is therefore the albedo
+        // weighted density of `sample`.
+        self.pdf(r_in, rec, wi) * spectrum::reflectance(&self.albedo, r_in)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> f64 {
+        if self.is_specular() || vec3::dot(wi, &rec.normal) <= 0.0 {
+            return 0.0;
+        }
+        let reflected =
+            vec3::reflect(&vec3::unit_vector(r_in.direction()), &rec.normal);
+        self.fuzz_pdf(&reflected, wi)
+    }
+
+    fn is_specular(&self) -> bool {
+        self.fuzz <= 0.0
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+}
+
+// Rough conductor described by its complex index of refraction (eta + i k),
+// with a GGX microfacet distribution sampled through its visible normals.
+pub struct Conductor {
+    eta: Color,
+    k: Color,
+    distribution: TrowbridgeReitz,
+}
+
+impl Conductor {
+    pub fn from(eta: Color, k: Color, roughness: f64) -> Self {
+        let alpha = TrowbridgeReitz::roughness_to_alpha(roughness);
+        Self {
+            eta,
+            k,
+            distribution: TrowbridgeReitz::from(alpha, alpha),
+        }
+    }
+
+    // Measured constants evaluated at roughly 650nm, 550nm and 450nm, the
+    // wavelengths in `spectrum::RGB_WAVELENGTHS`. Spectral renders
+    // interpolate between them.
+    pub fn gold(roughness: f64) -> Self {
+        Self::from(
+            Color::from(0.143, 0.374, 1.442),
+            Color::from(3.983, 2.385, 1.603),
+            roughness,
+        )
+    }
+
+    pub fn copper(roughness: f64) -> Self {
+        Self::from(
+            Color::from(0.200, 0.924, 1.102),
+            Color::from(3.912, 2.452, 2.142),
+            roughness,
+        )
+    }
+
+    pub fn aluminum(roughness: f64) -> Self {
+        Self::from(
+            Color::from(1.657, 0.880, 0.521),
+            Color::from(9.224, 6.270, 4.837),
+            roughness,
+        )
+    }
+
+    fn ior(&self, r_in: &Ray) -> (Color, Color) {
+        // Complex index of refraction for the channels of `r_in`
+        (
+            spectrum::interpolate(&self.eta, r_in),
+            spectrum::interpolate(&self.k, r_in),
+        )
+    }
+}
+
+impl Material for Conductor {
+    fn sample(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
+        let uvw = Onb::build_from_w(&rec.normal);
+        let wo = uvw.to_local(&-vec3::unit_vector(r_in.direction()));
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let (eta, k) = self.ior(r_in);
+        if self.distribution.effectively_smooth() {
+            // Perfect mirror, only the Fresnel term remains
+            let wi = Vec3::from(-wo.x(), -wo.y(), wo.z());
+            return Some(ScatterRecord {
+                scattered: r_in.spawn(rec.p, uvw.local(&wi)),
+                attenuation: microfacet::fresnel_complex_color(
+                    wo.z(),
+                    &eta,
+                    &k,
+                ),
+                pdf: 0.0,
+                is_specular: true,
+            });
+        }
+
+        let (u1, u2) = sampler.get_2d();
+        let wm = self.distribution.sample_wm(&wo, u1, u2);
+        let wi = vec3::reflect(&-wo, &wm);
+        if wi.z() <= 0.0 {
+            return None;
+        }
+
+        // With visible normal sampling the D term cancels out and the
+        // estimator weight reduces to F * G2 / G1.
+        let fresnel = microfacet::fresnel_complex_color(
+            vec3::dot(&wo, &wm).abs(),
+            &eta,
+            &k,
+        );
+        let scattered = r_in.spawn(rec.p, uvw.local(&wi));
+        Some(ScatterRecord {
+            scattered,
+            attenuation: fresnel
+                * (self.distribution.g(&wo, &wi) / self.distribution.g1(&wo)),
+            pdf: self.pdf(r_in, rec, &scattered.direction()),
+            is_specular: false,
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Color {
+        let uvw = Onb::build_from_w(&rec.normal);
+        let wo = uvw.to_local(&-vec3::unit_vector(r_in.direction()));
+        let wi = uvw.to_local(&vec3::unit_vector(*wi));
+        if self.is_specular() || wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Color::new();
+        }
+
+        let wm = vec3::unit_vector(wo + wi);
+        let (eta, k) = self.ior(r_in);
+        let fresnel =
+            microfacet::fresnel_complex_color(vec3::dot(&wo, &wm), &eta, &k);
+        let dg = self.distribution.d(&wm) * self.distribution.g(&wo, &wi);
+        (dg / (4.0 * wo.z())) * fresnel
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> f64 {
+        let uvw = Onb::build_from_w(&rec.normal);
+        let wo = uvw.to_local(&-vec3::unit_vector(r_in.direction()));
+        let wi = uvw.to_local(&vec3::unit_vector(*wi));
+        if self.is_specular() || wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return 0.0;
+        }
+
+        let wm = vec3::unit_vector(wo + wi);
+        self.distribution.d_visible(&wo, &wm) / (4.0 * vec3::dot(&wo, &wm))
+    }
+
+    fn is_specular(&self) -> bool {
+        self.distribution.effectively_smooth()
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        // Reflectance at normal incidence
+        let reflectance = |eta: f64, k: f64| {
+            ((eta - 1.0) * (eta - 1.0) + k * k)
+                / ((eta + 1.0) * (eta + 1.0) + k * k)
+        };
+        Color::from(
+            reflectance(self.eta.x(), self.k.x()),
+            reflectance(self.eta.y(), self.k.y()),
+            reflectance(self.eta.z(), self.k.z()),
+        )
+    }
+}
+
+// Wavelength in nanometers at which non-spectral renders evaluate the index
+// of refraction (the sodium D line, where catalogue values are quoted).
+pub const REFERENCE_WAVELENGTH: f64 = 589.3;
+
+#[derive(Debug, Copy, Clone)]
+pub enum Dispersion {
+    // n(λ) = a + b / λ², with λ in micrometers
+    Cauchy { a: f64, b: f64 },
+    // n²(λ) = 1 + Σ b_i λ² / (λ² - c_i), with λ in micrometers
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    pub fn bk7() -> Self {
+        // Schott N-BK7 borosilicate crown glass
+        Self::Sellmeier {
+            b: [1.03961212, 0.231792344, 1.01046945],
+            c: [0.00600069867, 0.0200179144, 103.560653],
+        }
+    }
+
+    pub fn diamond() -> Self {
+        Self::Sellmeier {
+            b: [0.3306, 4.3356, 0.0],
+            c: [0.030625, 0.011236, 0.0],
+        }
+    }
+
+    pub fn ior(&self, wavelength: f64) -> f64 {
+        // Index of refraction at `wavelength`, given in nanometers
+        let l = wavelength / 1000.0;
+        let l2 = l * l;
+        match self {
+            Self::Cauchy { a, b } => a + b / l2,
+            Self::Sellmeier { b, c } => {
+                let sum: f64 = b
+                    .iter()
+                    .zip(c.iter())
+                    .map(|(b, c)| b * l2 / (l2 - c))
+                    .sum();
+                (1.0 + sum).sqrt()
+            }
+        }
     }
 }
 
 pub struct Dielectric {
-    ir: f64, // Index of Refraction
+    ir: f64,           // Index of Refraction
+    absorption: Color, // Beer-Lambert absorption coefficient per unit length
+    dispersion: Option<Dispersion>,
 }
 
 impl Dielectric {
     pub fn from(ir: f64) -> Self {
-        Self { ir }
+        Self {
+            ir,
+            absorption: Color::new(),
+            dispersion: None,
+        }
+    }
+
+    pub fn absorbing(ir: f64, absorption: Color) -> Self {
+        Self {
+            ir,
+            absorption,
+            dispersion: None,
+        }
+    }
+
+    pub fn dispersive(dispersion: Dispersion, absorption: Color) -> Self {
+        Self {
+            ir: dispersion.ior(REFERENCE_WAVELENGTH),
+            absorption,
+            dispersion: Some(dispersion),
+        }
+    }
+
+    pub fn ior(&self, wavelength: f64) -> f64 {
+        match &self.dispersion {
+            Some(dispersion) => dispersion.ior(wavelength),
+            None => self.ir,
+        }
+    }
+
+    fn transmittance(&self, ray: &Ray, distance: f64) -> Color {
+        // Fraction of light surviving `distance` units inside the medium
+        let absorption = spectrum::reflectance(&self.absorption, ray);
+        Color::from(
+            (-absorption.x() * distance).exp(),
+            (-absorption.y() * distance).exp(),
+            (-absorption.z() * distance).exp(),
+        )
     }
 
     fn reflectance(&self, cosine: f64, ref_idx: f64) -> f64 {
@@ -94,18 +472,23 @@ impl Dielectric {
     }
 }
 impl Material for Dielectric {
-    fn scatter(
+    fn sample(
         &self,
         ray: &Ray,
         rec: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool {
-        let refraction_ratio = if rec.front_face {
-            1.0 / self.ir
-        } else {
-            self.ir
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
+        // In spectral mode a dispersive medium bends each wavelength its own
+        // way. The path follows the hero wavelength, and the others drop out.
+        let mut scattered_wavelengths = ray.wavelengths();
+        let ir = match (&self.dispersion, ray.wavelengths()) {
+            (Some(dispersion), Some(wavelengths)) => {
+                scattered_wavelengths = Some(wavelengths.terminate_secondary());
+                dispersion.ior(wavelengths.hero())
+            }
+            _ => self.ior(REFERENCE_WAVELENGTH),
         };
+        let refraction_ratio = if rec.front_face { 1.0 / ir } else { ir };
 
         let unit_direction = vec3::unit_vector(ray.direction());
 
@@ -117,7 +500,7 @@ impl Material for Dielectric {
 
         if cannot_refract
             || self.reflectance(cos_theta, refraction_ratio)
-                > utils::random_double()
+                > utils::random_double(sampler)
         {
             direction = vec3::reflect(&unit_direction, &rec.normal);
         } else {
@@ -125,9 +508,441 @@ impl Material for Dielectric {
                 vec3::refract(&unit_direction, &rec.normal, refraction_ratio);
         }
 
-        *attenuation = Color::from(1.0, 1.0, 1.0);
-        *scattered = Ray::from(rec.p, direction);
+        // Hitting the back face means the incoming ray travelled through the
+        // medium and was absorbed along the way.
+        let attenuation = if rec.front_face {
+            Color::from(1.0, 1.0, 1.0)
+        } else {
+            self.transmittance(ray, rec.t * ray.direction().length())
+        };
+
+        Some(ScatterRecord {
+            scattered: Ray::from(rec.p, direction)
+                .with_wavelengths(scattered_wavelengths),
+            attenuation,
+            pdf: 0.0,
+            is_specular: true,
+        })
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+}
+
+// Dielectric with a GGX rough interface, after Walter et al., "Microfacet
+// Models for Refraction through Rough Surfaces" (2007). Uses the exact
+// Fresnel equations and tints transmitted light by `tint`.
+pub struct RoughDielectric {
+    ir: f64, // Index of Refraction
+    tint: Color,
+    distribution: TrowbridgeReitz,
+}
+
+impl RoughDielectric {
+    pub fn from(ir: f64, roughness: f64, tint: Color) -> Self {
+        let alpha = TrowbridgeReitz::roughness_to_alpha(roughness);
+        Self {
+            ir,
+            tint,
+            distribution: TrowbridgeReitz::from(alpha, alpha),
+        }
+    }
+}
+
+impl RoughDielectric {
+    fn local_frame(&self, r_in: &Ray, rec: &HitRecord) -> (Onb, Vec3, f64) {
+        // Shading frame, outgoing direction and relative index of refraction
+        // across the interface
+        let uvw = Onb::build_from_w(&rec.normal);
+        let wo = uvw.to_local(&-vec3::unit_vector(r_in.direction()));
+        let eta = if rec.front_face {
+            self.ir
+        } else {
+            1.0 / self.ir
+        };
+        (uvw, wo, eta)
+    }
+}
+
+impl Material for RoughDielectric {
+    fn sample(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
+        let (uvw, wo, eta) = self.local_frame(r_in, rec);
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        if self.is_specular() {
+            // Smooth interface: choose between reflection and transmission
+            // proportionally to the Fresnel term, which then cancels out.
+            let reflectance = microfacet::fresnel_dielectric(wo.z(), eta);
+            let normal = Vec3::from(0.0, 0.0, 1.0);
+            let (wi, attenuation) =
+                if utils::random_double(sampler) < reflectance {
+                    (vec3::reflect(&-wo, &normal), Color::from(1.0, 1.0, 1.0))
+                } else {
+                    (
+                        vec3::refract(&-wo, &normal, 1.0 / eta),
+                        spectrum::reflectance(&self.tint, r_in),
+                    )
+                };
+            return Some(ScatterRecord {
+                scattered: r_in.spawn(rec.p, uvw.local(&wi)),
+                attenuation,
+                pdf: 0.0,
+                is_specular: true,
+            });
+        }
+
+        let uc = utils::random_double(sampler);
+        let (u1, u2) = sampler.get_2d();
+        let wi = self.distribution.dielectric_sample(eta, &wo, uc, u1, u2)?;
+        let scattered = r_in.spawn(rec.p, uvw.local(&wi));
+        let pdf = self.pdf(r_in, rec, &scattered.direction());
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(ScatterRecord {
+            scattered,
+            attenuation: self.eval(r_in, rec, &scattered.direction()) / pdf,
+            pdf,
+            is_specular: false,
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Color {
+        let (uvw, wo, eta) = self.local_frame(r_in, rec);
+        let wi = uvw.to_local(&vec3::unit_vector(*wi));
+        if self.is_specular() || wo.z() <= 0.0 {
+            return Color::new();
+        }
+
+        let f = self.distribution.dielectric_f(eta, &wo, &wi) * wi.z().abs();
+        if wi.z() > 0.0 {
+            Color::from(f, f, f)
+        } else {
+            f * spectrum::reflectance(&self.tint, r_in)
+        }
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> f64 {
+        let (uvw, wo, eta) = self.local_frame(r_in, rec);
+        let wi = uvw.to_local(&vec3::unit_vector(*wi));
+        if self.is_specular() || wo.z() <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.dielectric_pdf(eta, &wo, &wi)
+    }
+
+    fn is_specular(&self) -> bool {
+        self.distribution.effectively_smooth()
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.tint
+    }
+}
+
+// Thin transparent film in air, such as a soap bubble. Its colors come from
+// interference between the light reflected off its two faces, which depends
+// on the wavelength, so RGB renders only approximate them by evaluating the
+// film at `spectrum::RGB_WAVELENGTHS`.
+pub struct ThinFilm {
+    thickness: f64, // In nanometers
+    ir: f64,        // Index of Refraction
+}
+
+impl ThinFilm {
+    pub fn from(thickness: f64, ir: f64) -> Self {
+        Self { thickness, ir }
+    }
+
+    fn reflectance(&self, cos_theta: f64, wavelength: f64) -> f64 {
+        // Airy summation of the light bouncing back and forth inside the
+        // film, averaged over both polarizations
+        let sin2_t = (1.0 - cos_theta * cos_theta) / (self.ir * self.ir);
+        let cos_t = (1.0 - sin2_t).max(0.0).sqrt();
+        let r_s = (cos_theta - self.ir * cos_t) / (cos_theta + self.ir * cos_t);
+        let r_p = (self.ir * cos_theta - cos_t) / (self.ir * cos_theta + cos_t);
+
+        // Phase difference between successive reflections
+        let phase = 4.0 * PI * self.ir * self.thickness * cos_t / wavelength;
+        let airy = |r: f64| {
+            let r2 = r * r;
+            2.0 * r2 * (1.0 - phase.cos())
+                / (1.0 - 2.0 * r2 * phase.cos() + r2 * r2)
+        };
+        0.5 * (airy(r_s) + airy(r_p))
+    }
+}
+
+impl Material for ThinFilm {
+    fn sample(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
+        let unit_direction = vec3::unit_vector(r_in.direction());
+        let cos_theta = vec3::dot(&-unit_direction, &rec.normal).min(1.0);
+        let [l0, l1, l2] = spectrum::channel_wavelengths(r_in);
+        let reflectance = Color::from(
+            self.reflectance(cos_theta, l0),
+            self.reflectance(cos_theta, l1),
+            self.reflectance(cos_theta, l2),
+        );
+
+        // Reflect or pass straight through the film, choosing by the
+        // average reflectance
+        let p_reflect =
+            (reflectance.x() + reflectance.y() + reflectance.z()) / 3.0;
+        let (direction, attenuation) = if utils::random_double(sampler)
+            < p_reflect
+        {
+            (
+                vec3::reflect(&unit_direction, &rec.normal),
+                reflectance / p_reflect,
+            )
+        } else {
+            (
+                unit_direction,
+                (Color::from(1.0, 1.0, 1.0) - reflectance) / (1.0 - p_reflect),
+            )
+        };
+
+        Some(ScatterRecord {
+            scattered: r_in.spawn(rec.p, direction),
+            attenuation,
+            pdf: 0.0,
+            is_specular: true,
+        })
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::principled::Principled;
+    use crate::sampler::IndependentSampler;
+    use crate::texture::SolidColor;
+    use crate::vec3::Point3;
+    use std::rc::Rc;
+
+    fn hit_record(r_in: &Ray, outward_normal: Vec3) -> HitRecord {
+        let mut rec = HitRecord::new();
+        rec.t = 1.0;
+        rec.p = r_in.at(rec.t);
+        rec.set_face_normal(r_in, outward_normal);
+        rec
+    }
+
+    fn incoming_rays() -> Vec<(Ray, HitRecord)> {
+        // A head-on hit, a grazing hit and a hit from inside the surface
+        [
+            (Vec3::from(0.0, 0.0, -1.0), Vec3::from(0.0, 0.0, 1.0)),
+            (Vec3::from(1.0, 0.3, -0.2), Vec3::from(0.0, 0.0, 1.0)),
+            (Vec3::from(-0.4, 0.2, 1.0), Vec3::from(0.0, 0.0, 1.0)),
+        ]
+        .into_iter()
+        .map(|(direction, normal)| {
+            let ray = Ray::from(Point3::new() - direction, direction);
+            let rec = hit_record(&ray, normal);
+            (ray, rec)
+        })
+        .collect()
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!(
+            (a - b).abs() <= 1e-6 * a.abs().max(b.abs()).max(1.0),
+            "{} != {}",
+            a,
+            b
+        );
+    }
+
+    fn assert_eval_matches_sample(material: &dyn Material) {
+        assert!(!material.is_specular());
+        let mut sampler = IndependentSampler::new();
+
+        for (r_in, rec) in incoming_rays() {
+            let mut samples = 0;
+            for _ in 0..10_000 {
+                let srec = match material.sample(&r_in, &rec, &mut sampler) {
+                    Some(srec) => srec,
+                    None => continue,
+                };
+                samples += 1;
+                assert!(!srec.is_specular);
+
+                let wi = srec.scattered.direction();
+                let pdf = material.pdf(&r_in, &rec, &wi);
+                assert!(pdf > 0.0);
+                assert_close(pdf, srec.pdf);
+
+                let weight = material.eval(&r_in, &rec, &wi) / pdf;
+                for i in 0..3 {
+                    assert_close(weight.e[i], srec.attenuation.e[i]);
+                }
+            }
+            assert!(samples > 0);
+        }
+    }
+
+    fn assert_specular(material: &dyn Material) {
+        assert!(material.is_specular());
+        let mut sampler = IndependentSampler::new();
+
+        for (r_in, rec) in incoming_rays() {
+            for _ in 0..100 {
+                if let Some(srec) = material.sample(&r_in, &rec, &mut sampler) {
+                    let wi = srec.scattered.direction();
+                    assert!(srec.is_specular);
+                    assert_eq!(material.pdf(&r_in, &rec, &wi), 0.0);
+                    assert_eq!(material.eval(&r_in, &rec, &wi).length(), 0.0);
+                }
+            }
+        }
+    }
+
+    fn assert_pdf_normalized(material: &dyn Material) {
+        // The pdf integrated over the sphere should equal the probability
+        // that `sample` produces a direction at all.
+        let mut sampler = IndependentSampler::new();
+        let n = 100_000;
+        for (r_in, rec) in incoming_rays() {
+            let mut integral = 0.0;
+            let mut accepted = 0;
+            for _ in 0..n {
+                let wi = vec3::random_unit_vector(&mut sampler);
+                integral += material.pdf(&r_in, &rec, &wi) * 4.0 * PI;
+                if material.sample(&r_in, &rec, &mut sampler).is_some() {
+                    accepted += 1;
+                }
+            }
+            let integral = integral / n as f64;
+            let accepted = accepted as f64 / n as f64;
+            assert!(
+                (integral - accepted).abs() < 0.05,
+                "{} != {}",
+                integral,
+                accepted
+            );
+        }
+    }
+
+    #[test]
+    fn pdfs_are_normalized() {
+        assert_pdf_normalized(&Lambertian::from(Color::from(0.5, 0.5, 0.5)));
+        assert_pdf_normalized(&Metal::from(Color::from(0.5, 0.5, 0.5), 0.7));
+        assert_pdf_normalized(&Conductor::copper(0.7));
+        assert_pdf_normalized(&RoughDielectric::from(
+            1.5,
+            0.7,
+            Color::from(1.0, 1.0, 1.0),
+        ));
+        assert_pdf_normalized(&Principled::new());
+    }
+
+    #[test]
+    fn lambertian_eval_matches_sample() {
+        assert_eval_matches_sample(&Lambertian::from(Color::from(
+            0.8, 0.5, 0.2,
+        )));
+    }
+
+    #[test]
+    fn fuzzy_metal_pdf_matches_sample() {
+        // `eval` is the albedo times the pdf, so check the pdf against a
+        // histogram of sampled directions, binned by their angle to the
+        // mirror direction, and the pdf integrated over the same bins
+        let mut sampler = IndependentSampler::new();
+        let n = 200_000;
+        let bins = 8;
+        for fuzz in [0.3, 1.0] {
+            let metal = Metal::from(Color::from(0.9, 0.8, 0.7), fuzz);
+            for (r_in, rec) in incoming_rays() {
+                let reflected = vec3::reflect(
+                    &vec3::unit_vector(r_in.direction()),
+                    &rec.normal,
+                );
+                let bin = |wi: &Vec3| {
+                    let cosine = vec3::dot(&vec3::unit_vector(*wi), &reflected);
+                    (((1.0 - cosine) / 2.0 * bins as f64) as usize)
+                        .min(bins - 1)
+                };
+                let mut sampled = vec![0.0; bins];
+                let mut integrated = vec![0.0; bins];
+                for _ in 0..n {
+                    if let Some(srec) = metal.sample(&r_in, &rec, &mut sampler)
+                    {
+                        sampled[bin(&srec.scattered.direction())] += 1.0;
+                    }
+                    let wi = vec3::random_unit_vector(&mut sampler);
+                    integrated[bin(&wi)] +=
+                        metal.pdf(&r_in, &rec, &wi) * 4.0 * PI;
+                }
+                for k in 0..bins {
+                    let (a, b) =
+                        (sampled[k] / n as f64, integrated[k] / n as f64);
+                    assert!((a - b).abs() < 0.02, "bin {}: {} != {}", k, a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rough_conductor_eval_matches_sample() {
+        assert_eval_matches_sample(&Conductor::gold(0.4));
+        assert_eval_matches_sample(&Conductor::aluminum(0.05));
+    }
+
+    #[test]
+    fn rough_dielectric_eval_matches_sample() {
+        assert_eval_matches_sample(&RoughDielectric::from(
+            1.5,
+            0.3,
+            Color::from(0.9, 0.9, 0.6),
+        ));
+    }
+
+    #[test]
+    fn principled_eval_matches_sample() {
+        let mut plastic = Principled::new();
+        plastic.sheen = Rc::new(SolidColor::scalar(0.5));
+        plastic.clearcoat = Rc::new(SolidColor::scalar(1.0));
+        assert_eval_matches_sample(&plastic);
+
+        let mut brushed = Principled::new();
+        brushed.metallic = Rc::new(SolidColor::scalar(1.0));
+        brushed.anisotropic = Rc::new(SolidColor::scalar(0.8));
+        assert_eval_matches_sample(&brushed);
+
+        let mut glass = Principled::new();
+        glass.transmission = Rc::new(SolidColor::scalar(1.0));
+        glass.roughness = Rc::new(SolidColor::scalar(0.2));
+        assert_eval_matches_sample(&glass);
+    }
 
-        return true;
+    #[test]
+    fn delta_materials_are_specular() {
+        assert_specular(&Metal::from(Color::from(0.9, 0.8, 0.7), 0.0));
+        assert_specular(&Conductor::copper(0.0));
+        assert_specular(&Dielectric::from(1.5));
+        assert_specular(&ThinFilm::from(400.0, 1.33));
+        assert_specular(&RoughDielectric::from(
+            1.5,
+            0.0,
+            Color::from(1.0, 1.0, 1.0),
+        ));
     }
 }
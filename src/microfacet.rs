@@ -0,0 +1,321 @@
+use crate::vec3;
+use crate::vec3::{Color, Vec3};
+
+use std::f64::consts::PI;
+use std::ops;
+
+// Trowbridge-Reitz (GGX) microfacet distribution. All directions are
+// expressed in the local shading frame, where the macro surface normal is +z.
+pub struct TrowbridgeReitz {
+    alpha_x: f64,
+    alpha_y: f64,
+}
+
+impl TrowbridgeReitz {
+    pub fn from(alpha_x: f64, alpha_y: f64) -> Self {
+        Self { alpha_x, alpha_y }
+    }
+
+    pub fn roughness_to_alpha(roughness: f64) -> f64 {
+        // Perceptually linear roughness, as popularised by Burley.
+        roughness.clamp(0.0, 1.0).powi(2)
+    }
+
+    pub fn effectively_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 1e-3
+    }
+
+    pub fn d(&self, wm: &Vec3) -> f64 {
+        // Normal distribution function
+        if wm.z() <= 0.0 {
+            return 0.0;
+        }
+        let x = wm.x() / self.alpha_x;
+        let y = wm.y() / self.alpha_y;
+        let denom = x * x + y * y + wm.z() * wm.z();
+        1.0 / (PI * self.alpha_x * self.alpha_y * denom * denom)
+    }
+
+    pub fn lambda(&self, w: &Vec3) -> f64 {
+        let z2 = w.z() * w.z();
+        if z2 == 0.0 {
+            return f64::INFINITY;
+        }
+        let x = w.x() * self.alpha_x;
+        let y = w.y() * self.alpha_y;
+        let alpha2_tan2 = (x * x + y * y) / z2;
+        ((1.0 + alpha2_tan2).sqrt() - 1.0) / 2.0
+    }
+
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        // Smith masking function
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        // Height-correlated masking-shadowing function
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    pub fn d_visible(&self, w: &Vec3, wm: &Vec3) -> f64 {
+        // Density of the normals visible from direction `w`, which is also
+        // the density `sample_wm` draws from.
+        let cos_theta = w.z().abs();
+        if cos_theta == 0.0 {
+            return 0.0;
+        }
+        self.g1(w) / cos_theta * self.d(wm) * vec3::dot(w, wm).abs()
+    }
+
+    pub fn sample_wm(&self, w: &Vec3, u1: f64, u2: f64) -> Vec3 {
+        // Samples a visible normal following Heitz, "Sampling the GGX
+        // Distribution of Visible Normals" (2018).
+
+        // Transform w to the hemispherical configuration
+        let mut wh = vec3::unit_vector(Vec3::from(
+            self.alpha_x * w.x(),
+            self.alpha_y * w.y(),
+            w.z(),
+        ));
+        if wh.z() < 0.0 {
+            wh = -wh;
+        }
+
+        // Orthonormal basis around the stretched direction
+        let t1 = if wh.z() < 0.99999 {
+            vec3::unit_vector(vec3::cross(&Vec3::from(0.0, 0.0, 1.0), &wh))
+        } else {
+            Vec3::from(1.0, 0.0, 0.0)
+        };
+        let t2 = vec3::cross(&wh, &t1);
+
+        // Uniform point on the disk, warped to the projected hemisphere
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let px = r * phi.cos();
+        let mut py = r * phi.sin();
+        let h = (1.0 - px * px).sqrt();
+        let s = (1.0 + wh.z()) / 2.0;
+        py = (1.0 - s) * h + s * py;
+        let pz = (1.0 - px * px - py * py).max(0.0).sqrt();
+
+        // Back to the ellipsoid configuration
+        let nh = px * t1 + py * t2 + pz * wh;
+        vec3::unit_vector(Vec3::from(
+            self.alpha_x * nh.x(),
+            self.alpha_y * nh.y(),
+            nh.z().max(1e-6),
+        ))
+    }
+
+    // The functions below model a rough dielectric interface with relative
+    // index of refraction `eta` (transmitted over incident side), following
+    // Walter et al., "Microfacet Models for Refraction through Rough
+    // Surfaces" (2007). `wo` is assumed to lie in the upper hemisphere.
+
+    fn dielectric_half_vector(
+        &self,
+        eta: f64,
+        wo: &Vec3,
+        wi: &Vec3,
+    ) -> Option<Vec3> {
+        // Generalized half vector, shared by reflection and transmission
+        let reflect = wi.z() > 0.0;
+        let etap = if reflect { 1.0 } else { eta };
+        let wm = etap * *wi + *wo;
+        if wi.z() == 0.0 || wo.z() <= 0.0 || wm.near_zero() {
+            return None;
+        }
+        let mut wm = vec3::unit_vector(wm);
+        if wm.z() < 0.0 {
+            wm = -wm;
+        }
+
+        // Discard back facing microfacets
+        if vec3::dot(&wm, wi) * wi.z() < 0.0 || vec3::dot(&wm, wo) < 0.0 {
+            return None;
+        }
+        Some(wm)
+    }
+
+    pub fn dielectric_f(&self, eta: f64, wo: &Vec3, wi: &Vec3) -> f64 {
+        // BSDF value, without the cosine term
+        let wm = match self.dielectric_half_vector(eta, wo, wi) {
+            Some(wm) => wm,
+            None => return 0.0,
+        };
+        let fresnel = fresnel_dielectric(vec3::dot(wo, &wm), eta);
+        let d = self.d(&wm) * self.g(wo, wi);
+
+        if wi.z() > 0.0 {
+            d * fresnel / (4.0 * wi.z() * wo.z()).abs()
+        } else {
+            let denom = vec3::dot(wi, &wm) + vec3::dot(wo, &wm) / eta;
+            let denom = denom * denom * wi.z() * wo.z();
+            d * (1.0 - fresnel)
+                * (vec3::dot(wi, &wm) * vec3::dot(wo, &wm) / denom).abs()
+        }
+    }
+
+    pub fn dielectric_pdf(&self, eta: f64, wo: &Vec3, wi: &Vec3) -> f64 {
+        let wm = match self.dielectric_half_vector(eta, wo, wi) {
+            Some(wm) => wm,
+            None => return 0.0,
+        };
+        let fresnel = fresnel_dielectric(vec3::dot(wo, &wm), eta);
+
+        if wi.z() > 0.0 {
+            self.d_visible(wo, &wm) / (4.0 * vec3::dot(wo, &wm).abs()) * fresnel
+        } else {
+            let denom = vec3::dot(wi, &wm) + vec3::dot(wo, &wm) / eta;
+            let dwm_dwi = vec3::dot(wi, &wm).abs() / (denom * denom);
+            self.d_visible(wo, &wm) * dwm_dwi * (1.0 - fresnel)
+        }
+    }
+
+    pub fn dielectric_sample(
+        &self,
+        eta: f64,
+        wo: &Vec3,
+        uc: f64,
+        u1: f64,
+        u2: f64,
+    ) -> Option<Vec3> {
+        // Picks reflection or transmission proportionally to the Fresnel
+        // term of a sampled visible normal.
+        let wm = self.sample_wm(wo, u1, u2);
+        let fresnel = fresnel_dielectric(vec3::dot(wo, &wm), eta);
+
+        if uc < fresnel {
+            let wi = vec3::reflect(&-*wo, &wm);
+            if wi.z() <= 0.0 {
+                return None;
+            }
+            Some(wi)
+        } else {
+            let wi = vec3::refract(&-*wo, &wm, 1.0 / eta);
+            if wi.z() >= 0.0 {
+                return None;
+            }
+            Some(vec3::unit_vector(wi))
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn from(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+    fn norm(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+    fn sqrt(self) -> Self {
+        let n = self.norm().sqrt();
+        if n == 0.0 {
+            return Self::from(0.0, 0.0);
+        }
+        let t1 = (0.5 * (n + self.re.abs())).sqrt();
+        let t2 = 0.5 * self.im / t1;
+        if self.re >= 0.0 {
+            Self::from(t1, t2)
+        } else {
+            Self::from(t2.abs(), t1.copysign(self.im))
+        }
+    }
+}
+
+impl ops::Add for Complex {
+    type Output = Self;
+    fn add(self, rhs: Complex) -> Self::Output {
+        Self::from(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl ops::Sub for Complex {
+    type Output = Self;
+    fn sub(self, rhs: Complex) -> Self::Output {
+        Self::from(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl ops::Mul for Complex {
+    type Output = Self;
+    fn mul(self, rhs: Complex) -> Self::Output {
+        Self::from(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+impl ops::Div for Complex {
+    type Output = Self;
+    fn div(self, rhs: Complex) -> Self::Output {
+        let scale = 1.0 / rhs.norm();
+        Self::from(
+            scale * (self.re * rhs.re + self.im * rhs.im),
+            scale * (self.im * rhs.re - self.re * rhs.im),
+        )
+    }
+}
+
+pub fn fresnel_complex(cos_theta_i: f64, eta: f64, k: f64) -> f64 {
+    // Unpolarized Fresnel reflectance of a conductor with complex index of
+    // refraction eta + i k.
+    let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+    let sin2_theta_i = 1.0 - cos_theta_i * cos_theta_i;
+    let cos_i = Complex::from(cos_theta_i, 0.0);
+    let eta = Complex::from(eta, k);
+
+    let sin2_theta_t = Complex::from(sin2_theta_i, 0.0) / (eta * eta);
+    let cos_theta_t = (Complex::from(1.0, 0.0) - sin2_theta_t).sqrt();
+
+    let r_parl = (eta * cos_i - cos_theta_t) / (eta * cos_i + cos_theta_t);
+    let r_perp = (cos_i - eta * cos_theta_t) / (cos_i + eta * cos_theta_t);
+
+    (r_parl.norm() + r_perp.norm()) / 2.0
+}
+
+pub fn fresnel_complex_color(
+    cos_theta_i: f64,
+    eta: &Color,
+    k: &Color,
+) -> Color {
+    Color::from(
+        fresnel_complex(cos_theta_i, eta.x(), k.x()),
+        fresnel_complex(cos_theta_i, eta.y(), k.y()),
+        fresnel_complex(cos_theta_i, eta.z(), k.z()),
+    )
+}
+
+pub fn fresnel_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    // Unpolarized Fresnel reflectance at a dielectric interface, where `eta`
+    // is the ratio of the transmitted to the incident index of refraction.
+    let mut cos_theta_i = cos_theta_i.clamp(-1.0, 1.0);
+    let mut eta = eta;
+    if cos_theta_i < 0.0 {
+        // Coming from the other side of the interface
+        eta = 1.0 / eta;
+        cos_theta_i = -cos_theta_i;
+    }
+
+    let sin2_theta_i = 1.0 - cos_theta_i * cos_theta_i;
+    let sin2_theta_t = sin2_theta_i / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        // Total internal reflection
+        return 1.0;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+
+    let r_parl =
+        (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perp =
+        (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    (r_parl * r_parl + r_perp * r_perp) / 2.0
+}
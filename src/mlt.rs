@@ -0,0 +1,310 @@
+use crate::camera::Camera;
+use crate::film::Film;
+use crate::hittable::HittableList;
+use crate::integrator::{Integrator, SimplePathTracer};
+use crate::light::LightList;
+use crate::sampler;
+use crate::sampler::Sampler;
+use crate::spectrum;
+use crate::vec3;
+use crate::vec3::Color;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::f64::consts::PI;
+
+// Primary sample space Metropolis light transport, after Kelemen et al.
+// (2002). A path is a function of the random numbers it consumes, so rather
+// than mutating paths directly, Markov chains wander over those numbers:
+// small perturbations explore the neighbourhood of paths that carry light,
+// such as the few that find their way through a narrow opening, while
+// occasional fresh samples keep the chains from getting stuck.
+pub struct Mlt {
+    pub max_depth: i32,
+    // Independent paths used to normalize the image and start the chains
+    pub bootstrap_samples: usize,
+    pub chains: usize,
+    pub mutations_per_pixel: i32,
+    // Standard deviation of a small step, in primary sample space
+    pub sigma: f64,
+    pub large_step_probability: f64,
+}
+
+impl Mlt {
+    pub fn new() -> Self {
+        Self {
+            max_depth: 10,
+            bootstrap_samples: 100_000,
+            chains: 1000,
+            mutations_per_pixel: 100,
+            sigma: 0.01,
+            large_step_probability: 0.3,
+        }
+    }
+
+    pub fn render_film(
+        &self,
+        camera: &mut Camera,
+        world: &HittableList,
+        lights: &LightList,
+    ) -> Film {
+        // The image is a sum of splats to be divided by
+        // `mutations_per_pixel`.
+        camera.initialize();
+        let mut film = camera.film();
+        let mut path_tracer = SimplePathTracer::new();
+        path_tracer.max_depth = self.max_depth;
+
+        // Bootstrap: the average brightness of independent paths gives the
+        // image's overall scale, and the chains start from paths picked in
+        // proportion to their brightness.
+        let mut cdf = Vec::with_capacity(self.bootstrap_samples);
+        let mut total = 0.0;
+        for index in 0..self.bootstrap_samples {
+            let mut sampler = self.sampler(camera, index);
+            let (l, _, _) = self.l(
+                &path_tracer,
+                camera,
+                world,
+                lights,
+                &mut film,
+                &mut sampler,
+            );
+            total += brightness(&l);
+            cdf.push(total);
+        }
+        if total <= 0.0 {
+            return film;
+        }
+        let b = total / self.bootstrap_samples as f64;
+
+        let pixels = camera.image_width as u64 * camera.image_height() as u64;
+        let mutations = self.mutations_per_pixel.max(0) as u64 * pixels;
+        let chains = self.chains.max(1) as u64;
+        for chain in 0..chains {
+            log::info!(r"Chains remaining: {} ", chains - chain);
+
+            // Replays the chosen bootstrap path to start the chain. Seeds
+            // past the bootstrap ones drive the accept decisions.
+            let mut rng = StdRng::seed_from_u64(sampler::hash(&[
+                camera.seed,
+                self.bootstrap_samples as u64 + chain,
+            ]));
+            let u: f64 = rng.gen();
+            let index =
+                cdf.partition_point(|&c| c <= u * total).min(cdf.len() - 1);
+            let mut sampler = self.sampler(camera, index);
+            let mut current = self.l(
+                &path_tracer,
+                camera,
+                world,
+                lights,
+                &mut film,
+                &mut sampler,
+            );
+
+            // Spreads the mutations evenly over the chains
+            let count =
+                (chain + 1) * mutations / chains - chain * mutations / chains;
+            for _ in 0..count {
+                sampler.start_iteration();
+                let proposed = self.l(
+                    &path_tracer,
+                    camera,
+                    world,
+                    lights,
+                    &mut film,
+                    &mut sampler,
+                );
+
+                // Both states are recorded, weighted by how likely each is
+                // to be the next one, which keeps rejected proposals useful.
+                let y_current = brightness(&current.0);
+                let y_proposed = brightness(&proposed.0);
+                let accept = if y_current > 0.0 {
+                    (y_proposed / y_current).min(1.0)
+                } else {
+                    1.0
+                };
+                if accept > 0.0 && y_proposed > 0.0 {
+                    film.add_splat(
+                        proposed.1,
+                        proposed.2,
+                        (accept * b / y_proposed) * proposed.0,
+                    );
+                }
+                if accept < 1.0 {
+                    film.add_splat(
+                        current.1,
+                        current.2,
+                        ((1.0 - accept) * b / y_current) * current.0,
+                    );
+                }
+
+                if rng.gen::<f64>() < accept {
+                    current = proposed;
+                    sampler.accept();
+                } else {
+                    sampler.reject();
+                }
+            }
+        }
+        film
+    }
+
+    fn sampler(&self, camera: &Camera, index: usize) -> MltSampler {
+        MltSampler::from(
+            sampler::hash(&[camera.seed, index as u64]),
+            self.sigma,
+            self.large_step_probability,
+        )
+    }
+
+    fn l(
+        &self,
+        path_tracer: &SimplePathTracer,
+        camera: &Camera,
+        world: &HittableList,
+        lights: &LightList,
+        film: &mut Film,
+        sampler: &mut MltSampler,
+    ) -> (Color, f64, f64) {
+        // Radiance along the path given by the sampler's current state, and
+        // the raster position it was traced through, which is part of that
+        // state too. The film spreads it over the pixels around that
+        // position through the camera's filter.
+        sampler.restart();
+        let x = sampler.get_1d() * camera.image_width as f64 - 0.5;
+        let y = sampler.get_1d() * camera.image_height() as f64 - 0.5;
+        let wavelengths = camera.sample_wavelengths(sampler);
+        let ray = camera
+            .get_ray_at(x, y, sampler)
+            .with_wavelengths(wavelengths);
+        let l = path_tracer.li(&ray, camera, world, lights, film, sampler);
+        (spectrum::to_rgb(&l, wavelengths), x, y)
+    }
+}
+
+fn brightness(color: &Color) -> f64 {
+    // Target density of the chains, zero for invalid samples
+    let y = vec3::luminance(color);
+    if y.is_finite() {
+        y.max(0.0)
+    } else {
+        0.0
+    }
+}
+
+// Largest f64 below one
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+struct PrimarySample {
+    value: f64,
+    // Iteration in which `value` last changed
+    last_modification: u64,
+    // State before the current mutation, restored if it's rejected
+    value_backup: f64,
+    modify_backup: u64,
+}
+
+// Sampler whose numbers are the state of a Markov chain. Samples are only
+// mutated when a path asks for them, catching up on the small steps they
+// missed in one go.
+struct MltSampler {
+    rng: StdRng,
+    sigma: f64,
+    large_step_probability: f64,
+    x: Vec<PrimarySample>,
+    current_iteration: u64,
+    large_step: bool,
+    last_large_step_iteration: u64,
+    sample_index: usize,
+}
+
+impl MltSampler {
+    fn from(seed: u64, sigma: f64, large_step_probability: f64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            sigma,
+            large_step_probability,
+            x: Vec::new(),
+            current_iteration: 0,
+            large_step: true,
+            last_large_step_iteration: 0,
+            sample_index: 0,
+        }
+    }
+
+    fn start_iteration(&mut self) {
+        self.current_iteration += 1;
+        self.large_step = self.rng.gen::<f64>() < self.large_step_probability;
+    }
+
+    fn restart(&mut self) {
+        // Starts consuming samples from the first one again
+        self.sample_index = 0;
+    }
+
+    fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step_iteration = self.current_iteration;
+        }
+    }
+
+    fn reject(&mut self) {
+        for xi in self.x.iter_mut() {
+            if xi.last_modification == self.current_iteration {
+                xi.value = xi.value_backup;
+                xi.last_modification = xi.modify_backup;
+            }
+        }
+        self.current_iteration -= 1;
+    }
+
+    fn ensure_ready(&mut self, index: usize) {
+        // Dimensions a path hasn't asked for before start out uniformly
+        // distributed, as if set by the last large step
+        while index >= self.x.len() {
+            let value = self.rng.gen();
+            self.x.push(PrimarySample {
+                value,
+                last_modification: self.last_large_step_iteration,
+                value_backup: value,
+                modify_backup: self.last_large_step_iteration,
+            });
+        }
+        let xi = &mut self.x[index];
+
+        // A sample untouched since the last accepted large step would have
+        // been replaced by it
+        if xi.last_modification < self.last_large_step_iteration {
+            xi.value = self.rng.gen();
+            xi.last_modification = self.last_large_step_iteration;
+        }
+
+        xi.value_backup = xi.value;
+        xi.modify_backup = xi.last_modification;
+        if self.large_step {
+            xi.value = self.rng.gen();
+        } else {
+            // The sum of the missed small steps is a single normally
+            // distributed step, wrapped around to stay in [0, 1)
+            let steps = (self.current_iteration - xi.last_modification) as f64;
+            let u1: f64 = self.rng.gen();
+            let u2: f64 = self.rng.gen();
+            let normal =
+                (-2.0 * (1.0 - u1).ln()).sqrt() * (2.0 * PI * u2).cos();
+            xi.value += normal * self.sigma * steps.sqrt();
+            xi.value = (xi.value - xi.value.floor()).min(ONE_MINUS_EPSILON);
+        }
+        xi.last_modification = self.current_iteration;
+    }
+}
+
+impl Sampler for MltSampler {
+    fn get_1d(&mut self) -> f64 {
+        self.ensure_ready(self.sample_index);
+        self.sample_index += 1;
+        self.x[self.sample_index - 1].value
+    }
+}
@@ -0,0 +1,41 @@
+use crate::vec3;
+use crate::vec3::Vec3;
+
+// Orthonormal basis used to move directions in and out of a local shading
+// frame where the surface normal is the +z axis.
+pub struct Onb {
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Onb {
+    pub fn build_from_w(n: &Vec3) -> Self {
+        // Branchless construction from Duff et al., "Building an Orthonormal
+        // Basis, Revisited" (2017).
+        let w = vec3::unit_vector(*n);
+        let sign = 1.0_f64.copysign(w.z());
+        let a = -1.0 / (sign + w.z());
+        let b = w.x() * w.y() * a;
+
+        let u =
+            Vec3::from(1.0 + sign * w.x() * w.x() * a, sign * b, -sign * w.x());
+        let v = Vec3::from(b, sign + w.y() * w.y() * a, -w.y());
+
+        Self { u, v, w }
+    }
+
+    pub fn local(&self, a: &Vec3) -> Vec3 {
+        // Converts a vector expressed in this basis to world space.
+        a.x() * self.u + a.y() * self.v + a.z() * self.w
+    }
+
+    pub fn to_local(&self, a: &Vec3) -> Vec3 {
+        // Converts a world space vector to this basis.
+        Vec3::from(
+            vec3::dot(a, &self.u),
+            vec3::dot(a, &self.v),
+            vec3::dot(a, &self.w),
+        )
+    }
+}
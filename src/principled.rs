@@ -0,0 +1,359 @@
+use crate::hittable::HitRecord;
+use crate::material::{Material, ScatterRecord};
+use crate::microfacet::TrowbridgeReitz;
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::spectrum;
+use crate::texture::{SolidColor, Texture};
+use crate::utils;
+use crate::vec3;
+use crate::vec3::{Color, Vec3};
+
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+// Fixed parameters of the original model that are not exposed to artists.
+const SHEEN_TINT: f64 = 0.5;
+const CLEARCOAT_ALPHA: f64 = 0.001;
+
+// Principled BSDF after Burley, "Physically Based Shading at Disney" (2012),
+// extended with rough transmission as in the 2015 course notes. Every
+// parameter is a texture so it can vary over the surface; scalar parameters
+// read the first channel.
+pub struct Principled {
+    pub base_color: Rc<dyn Texture>,
+    pub metallic: Rc<dyn Texture>,
+    pub roughness: Rc<dyn Texture>,
+    pub specular: Rc<dyn Texture>,
+    pub specular_tint: Rc<dyn Texture>,
+    pub sheen: Rc<dyn Texture>,
+    pub clearcoat: Rc<dyn Texture>,
+    pub transmission: Rc<dyn Texture>,
+    pub anisotropic: Rc<dyn Texture>,
+    pub ior: f64,
+}
+
+impl Principled {
+    pub fn new() -> Self {
+        Self {
+            base_color: Rc::new(SolidColor::from(Color::from(0.8, 0.8, 0.8))),
+            metallic: Rc::new(SolidColor::scalar(0.0)),
+            roughness: Rc::new(SolidColor::scalar(0.5)),
+            specular: Rc::new(SolidColor::scalar(0.5)),
+            specular_tint: Rc::new(SolidColor::scalar(0.0)),
+            sheen: Rc::new(SolidColor::scalar(0.0)),
+            clearcoat: Rc::new(SolidColor::scalar(0.0)),
+            transmission: Rc::new(SolidColor::scalar(0.0)),
+            anisotropic: Rc::new(SolidColor::scalar(0.0)),
+            ior: 1.5,
+        }
+    }
+
+    fn lobes(&self, r_in: &Ray, rec: &HitRecord) -> Lobes {
+        let scalar = |texture: &Rc<dyn Texture>| {
+            texture.value(rec.u, rec.v, &rec.p).x().clamp(0.0, 1.0)
+        };
+
+        let base_color = self.base_color.value(rec.u, rec.v, &rec.p);
+        let metallic = scalar(&self.metallic);
+        let roughness = scalar(&self.roughness);
+        let transmission = scalar(&self.transmission);
+        let clearcoat = scalar(&self.clearcoat);
+
+        let lum = vec3::luminance(&base_color);
+        let tint = if lum > 0.0 {
+            base_color / lum
+        } else {
+            Color::from(1.0, 1.0, 1.0)
+        };
+
+        // Anisotropic specular roughness
+        let alpha = TrowbridgeReitz::roughness_to_alpha(roughness);
+        let aspect = (1.0 - 0.9 * scalar(&self.anisotropic)).sqrt();
+        let specular_distribution = TrowbridgeReitz::from(
+            (alpha / aspect).max(0.001),
+            (alpha * aspect).max(0.001),
+        );
+        let transmission_distribution =
+            TrowbridgeReitz::from(alpha.max(0.001), alpha.max(0.001));
+
+        let diffuse_weight = (1.0 - metallic) * (1.0 - transmission);
+        let transmission_weight = (1.0 - metallic) * transmission;
+        let specular_weight = 1.0 - transmission_weight;
+
+        // Lobe selection probabilities, a rough guess of each lobe's share
+        let mut p_diffuse = diffuse_weight;
+        let mut p_specular = specular_weight * (0.25 + 0.75 * metallic);
+        let mut p_clearcoat = 0.25 * clearcoat;
+        let mut p_transmission = transmission_weight;
+        let total = p_diffuse + p_specular + p_clearcoat + p_transmission;
+        p_diffuse /= total;
+        p_specular /= total;
+        p_clearcoat /= total;
+        p_transmission /= total;
+
+        let specular_tint = scalar(&self.specular_tint);
+        let dielectric_specular = scalar(&self.specular)
+            * 0.08
+            * lerp(Color::from(1.0, 1.0, 1.0), tint, specular_tint);
+
+        // Colors are mixed in RGB, then converted for spectral renders
+        let sheen_color = scalar(&self.sheen)
+            * lerp(Color::from(1.0, 1.0, 1.0), tint, SHEEN_TINT);
+        let specular_color = lerp(dielectric_specular, base_color, metallic);
+
+        Lobes {
+            base_color: spectrum::reflectance(&base_color, r_in),
+            sheen_color: spectrum::reflectance(&sheen_color, r_in),
+            specular_color: spectrum::reflectance(&specular_color, r_in),
+            roughness,
+            clearcoat,
+            diffuse_weight,
+            specular_weight,
+            transmission_weight,
+            specular_distribution,
+            transmission_distribution,
+            eta: if rec.front_face {
+                self.ior
+            } else {
+                1.0 / self.ior
+            },
+            p_diffuse,
+            p_specular,
+            p_clearcoat,
+            p_transmission,
+        }
+    }
+}
+
+impl Material for Principled {
+    fn sample(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
+        let (uvw, wo) = local_frame(r_in, rec);
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let lobes = self.lobes(r_in, rec);
+        let wi = lobes.sample(&wo, sampler)?;
+        let pdf = lobes.pdf(&wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        Some(ScatterRecord {
+            scattered: r_in.spawn(rec.p, uvw.local(&wi)),
+            attenuation: lobes.eval(&wo, &wi) / pdf,
+            pdf,
+            is_specular: false,
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Color {
+        let (uvw, wo) = local_frame(r_in, rec);
+        if wo.z() <= 0.0 {
+            return Color::new();
+        }
+        let wi = uvw.to_local(&vec3::unit_vector(*wi));
+        self.lobes(r_in, rec).eval(&wo, &wi)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> f64 {
+        let (uvw, wo) = local_frame(r_in, rec);
+        if wo.z() <= 0.0 {
+            return 0.0;
+        }
+        let wi = uvw.to_local(&vec3::unit_vector(*wi));
+        self.lobes(r_in, rec).pdf(&wo, &wi)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.base_color.value(rec.u, rec.v, &rec.p)
+    }
+}
+
+fn local_frame(r_in: &Ray, rec: &HitRecord) -> (Onb, Vec3) {
+    let uvw = Onb::build_from_w(&rec.normal);
+    let wo = uvw.to_local(&-vec3::unit_vector(r_in.direction()));
+    (uvw, wo)
+}
+
+// Principled parameters resolved at a single shading point. Directions are in
+// the local shading frame, with `wo` in the upper hemisphere.
+struct Lobes {
+    base_color: Color,
+    sheen_color: Color,
+    specular_color: Color,
+    roughness: f64,
+    clearcoat: f64,
+
+    diffuse_weight: f64,
+    specular_weight: f64,
+    transmission_weight: f64,
+
+    specular_distribution: TrowbridgeReitz,
+    transmission_distribution: TrowbridgeReitz,
+    eta: f64,
+
+    p_diffuse: f64,
+    p_specular: f64,
+    p_clearcoat: f64,
+    p_transmission: f64,
+}
+
+impl Lobes {
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        // BSDF value times the cosine of the incident direction
+        let mut f = Color::new();
+
+        if wi.z() > 0.0 {
+            let wh = vec3::unit_vector(*wo + *wi);
+            let cos_d = vec3::dot(wi, &wh);
+            let cos_o = wo.z();
+            let cos_i = wi.z();
+
+            if self.diffuse_weight > 0.0 {
+                // Diffuse with grazing retro-reflection, plus sheen
+                let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+                let fd = (1.0 + (fd90 - 1.0) * schlick_weight(cos_i))
+                    * (1.0 + (fd90 - 1.0) * schlick_weight(cos_o));
+                let diffuse = fd / PI * self.base_color
+                    + schlick_weight(cos_d) * self.sheen_color;
+                f += self.diffuse_weight * diffuse;
+            }
+
+            if self.specular_weight > 0.0 {
+                let fresnel = lerp(
+                    self.specular_color,
+                    Color::from(1.0, 1.0, 1.0),
+                    schlick_weight(cos_d),
+                );
+                let dg = self.specular_distribution.d(&wh)
+                    * self.specular_distribution.g(wo, wi);
+                f += (self.specular_weight * dg / (4.0 * cos_o * cos_i))
+                    * fresnel;
+            }
+
+            if self.clearcoat > 0.0 {
+                let fresnel = 0.04 + 0.96 * schlick_weight(cos_d);
+                let g = smith_g_ggx(cos_o, 0.25) * smith_g_ggx(cos_i, 0.25);
+                let value = 0.25
+                    * self.clearcoat
+                    * gtr1(wh.z(), CLEARCOAT_ALPHA)
+                    * fresnel
+                    * g
+                    / (4.0 * cos_o * cos_i);
+                f += Color::from(value, value, value);
+            }
+        }
+
+        if self.transmission_weight > 0.0 {
+            let value = self.transmission_weight
+                * self
+                    .transmission_distribution
+                    .dielectric_f(self.eta, wo, wi);
+            if wi.z() > 0.0 {
+                f += Color::from(value, value, value);
+            } else {
+                f += value * self.base_color;
+            }
+        }
+
+        f * wi.z().abs()
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        let mut pdf = 0.0;
+
+        if wi.z() > 0.0 {
+            let wh = vec3::unit_vector(*wo + *wi);
+            let cos_oh = vec3::dot(wo, &wh);
+            pdf += self.p_diffuse * wi.z() / PI;
+            if cos_oh > 0.0 {
+                pdf += self.p_specular
+                    * self.specular_distribution.d_visible(wo, &wh)
+                    / (4.0 * cos_oh);
+                pdf +=
+                    self.p_clearcoat * gtr1(wh.z(), CLEARCOAT_ALPHA) * wh.z()
+                        / (4.0 * cos_oh);
+            }
+        }
+
+        if self.p_transmission > 0.0 {
+            pdf += self.p_transmission
+                * self
+                    .transmission_distribution
+                    .dielectric_pdf(self.eta, wo, wi);
+        }
+
+        pdf
+    }
+
+    fn sample(&self, wo: &Vec3, sampler: &mut dyn Sampler) -> Option<Vec3> {
+        let mut u = utils::random_double(sampler);
+
+        if u < self.p_diffuse {
+            return Some(vec3::random_cosine_direction(sampler));
+        }
+        u -= self.p_diffuse;
+
+        if u < self.p_specular {
+            let (u1, u2) = sampler.get_2d();
+            let wm = self.specular_distribution.sample_wm(wo, u1, u2);
+            let wi = vec3::reflect(&-*wo, &wm);
+            return if wi.z() > 0.0 { Some(wi) } else { None };
+        }
+        u -= self.p_specular;
+
+        if u < self.p_clearcoat {
+            let (u1, u2) = sampler.get_2d();
+            let wh = sample_gtr1(CLEARCOAT_ALPHA, u1, u2);
+            let wi = vec3::reflect(&-*wo, &wh);
+            return if wi.z() > 0.0 { Some(wi) } else { None };
+        }
+
+        let uc = utils::random_double(sampler);
+        let (u1, u2) = sampler.get_2d();
+        self.transmission_distribution
+            .dielectric_sample(self.eta, wo, uc, u1, u2)
+    }
+}
+
+fn lerp(a: Color, b: Color, t: f64) -> Color {
+    (1.0 - t) * a + t * b
+}
+
+fn schlick_weight(cos_theta: f64) -> f64 {
+    (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+fn smith_g_ggx(cos_theta: f64, alpha: f64) -> f64 {
+    // Separable Smith masking term for an isotropic GGX lobe
+    let a = alpha * alpha;
+    let b = cos_theta * cos_theta;
+    2.0 * cos_theta / (cos_theta + (a + b - a * b).sqrt())
+}
+
+fn gtr1(cos_h: f64, alpha: f64) -> f64 {
+    // Generalized Trowbridge-Reitz distribution with gamma = 1, used by the
+    // clearcoat lobe.
+    if alpha >= 1.0 {
+        return 1.0 / PI;
+    }
+    let a2 = alpha * alpha;
+    let t = 1.0 + (a2 - 1.0) * cos_h * cos_h;
+    (a2 - 1.0) / (PI * a2.ln() * t)
+}
+
+fn sample_gtr1(alpha: f64, u1: f64, u2: f64) -> Vec3 {
+    let a2 = alpha * alpha;
+    let cos_theta = ((1.0 - a2.powf(1.0 - u1)) / (1.0 - a2)).max(0.0).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u2;
+    Vec3::from(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
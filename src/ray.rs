@@ -1,20 +1,31 @@
+use crate::spectrum::Wavelengths;
 use crate::vec3::{Point3, Vec3};
 
 #[derive(Debug, Copy, Clone)]
 pub struct Ray {
     orig: Point3,
     dir: Vec3,
+    // Wavelengths of the path the ray belongs to, in spectral mode
+    wavelengths: Option<Wavelengths>,
 }
 
 impl Ray {
-    pub fn new() -> Self {
+    pub fn from(orig: Point3, dir: Vec3) -> Self {
         Self {
-            orig: Point3::new(),
-            dir: Vec3::new(),
+            orig,
+            dir,
+            wavelengths: None,
         }
     }
-    pub fn from(orig: Point3, dir: Vec3) -> Self {
-        Self { orig, dir }
+    pub fn with_wavelengths(self, wavelengths: Option<Wavelengths>) -> Self {
+        Self {
+            wavelengths,
+            ..self
+        }
+    }
+    pub fn spawn(&self, orig: Point3, dir: Vec3) -> Self {
+        // Next ray along the same path, carrying its wavelengths
+        Self::from(orig, dir).with_wavelengths(self.wavelengths)
     }
     pub fn origin(&self) -> Point3 {
         self.orig
@@ -22,6 +33,9 @@ impl Ray {
     pub fn direction(&self) -> Vec3 {
         self.dir
     }
+    pub fn wavelengths(&self) -> Option<Wavelengths> {
+        self.wavelengths
+    }
     pub fn at(self, t: f64) -> Vec3 {
         return self.orig + t * self.dir;
     }
@@ -0,0 +1,522 @@
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+// Source of the random numbers consumed while tracing a path. Everything that
+// needs randomness takes one of these, rather than reaching for a global RNG,
+// so that an integrator can control the numbers a path is built from.
+pub trait Sampler {
+    // Restarts the numbers for sample `index` of pixel (i, j). They depend
+    // only on these and the sampler's seed, so a render comes out the same
+    // whatever order its samples are taken in. Samplers that aren't tied to
+    // pixels ignore this.
+    fn start_pixel_sample(&mut self, _i: i32, _j: i32, _index: i32) {}
+
+    // Returns the next sample in [0, 1)
+    fn get_1d(&mut self) -> f64;
+
+    // Returns the next pair of samples in [0, 1)^2, for quantities sampled
+    // in two dimensions together, such as a point on the lens or a
+    // direction. Low discrepancy samplers keep the pairs well distributed
+    // over the square, and not just each coordinate on its own.
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+// Pattern of the samples taken within each pixel.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Sampling {
+    // Independent uniform random numbers
+    Independent,
+    // One jittered sample in each cell of a grid over every dimension
+    Stratified,
+    // Halton sequence, randomized per pixel by Owen scrambling
+    Halton,
+    // Owen scrambled Sobol points, shuffled per dimension
+    Sobol,
+}
+
+impl Sampling {
+    pub fn sampler(
+        &self,
+        samples_per_pixel: i32,
+        seed: u64,
+    ) -> Box<dyn Sampler> {
+        match self {
+            Sampling::Independent => Box::new(IndependentSampler::from(seed)),
+            Sampling::Stratified => {
+                Box::new(StratifiedSampler::from(samples_per_pixel, seed))
+            }
+            Sampling::Halton => Box::new(HaltonSampler::from(seed)),
+            Sampling::Sobol => Box::new(SobolSampler::from(seed)),
+        }
+    }
+}
+
+// Independent uniform random numbers.
+pub struct IndependentSampler {
+    seed: u64,
+    rng: StdRng,
+}
+
+impl IndependentSampler {
+    pub fn new() -> Self {
+        Self::from(0)
+    }
+
+    pub fn from(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, i: i32, j: i32, index: i32) {
+        self.rng = StdRng::seed_from_u64(hash(&[
+            self.seed,
+            i as u64,
+            j as u64,
+            index as u64,
+        ]));
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.rng.gen()
+    }
+}
+
+// Stratified sampling, as in pbrt: every dimension is divided into as many
+// strata as there are samples per pixel, and each sample takes a jittered
+// point in one of them. A random permutation per pixel and dimension decides
+// which, so that the dimensions don't line up with each other.
+pub struct StratifiedSampler {
+    // Grid of strata for 2D samples, `x_samples` by `samples / x_samples`
+    samples: u32,
+    x_samples: u32,
+    seed: u64,
+    rng: StdRng,
+    pixel: (i32, i32),
+    index: u32,
+    dimension: u64,
+}
+
+impl StratifiedSampler {
+    pub fn from(samples_per_pixel: i32, seed: u64) -> Self {
+        // The squarest grid with exactly one stratum per sample
+        let samples = samples_per_pixel.max(1) as u32;
+        let x_samples = (1..=samples)
+            .take_while(|x| x * x <= samples)
+            .filter(|x| samples.is_multiple_of(*x))
+            .last()
+            .unwrap_or(1);
+        Self {
+            samples,
+            x_samples,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn stratum(&mut self) -> u32 {
+        // Stratum of the current sample in the next dimension
+        let h = hash(&[
+            self.pixel.0 as u64,
+            self.pixel.1 as u64,
+            self.dimension,
+            self.seed,
+        ]);
+        self.dimension += 1;
+        permutation_element(self.index % self.samples, self.samples, h as u32)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, i: i32, j: i32, index: i32) {
+        self.pixel = (i, j);
+        self.index = index as u32;
+        self.dimension = 0;
+        self.rng = StdRng::seed_from_u64(hash(&[
+            self.seed,
+            i as u64,
+            j as u64,
+            index as u64,
+        ]));
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let stratum = self.stratum();
+        let jitter: f64 = self.rng.gen();
+        ((stratum as f64 + jitter) / self.samples as f64).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let stratum = self.stratum();
+        let y_samples = self.samples / self.x_samples;
+        let x = stratum % self.x_samples;
+        let y = stratum / self.x_samples;
+        let (dx, dy): (f64, f64) = (self.rng.gen(), self.rng.gen());
+        (
+            ((x as f64 + dx) / self.x_samples as f64).min(ONE_MINUS_EPSILON),
+            ((y as f64 + dy) / y_samples as f64).min(ONE_MINUS_EPSILON),
+        )
+    }
+}
+
+// The Halton sequence takes the radical inverse of the sample index in a
+// different prime base for each dimension. Each pixel runs through its own
+// copy of the sequence, decorrelated from its neighbours' by Owen scrambling
+// the digits with a random seed per pixel and dimension.
+pub struct HaltonSampler {
+    seed: u64,
+    pixel: (i32, i32),
+    index: u64,
+    dimension: usize,
+}
+
+// Bases of the Halton dimensions. Later dimensions fall back to independent
+// random numbers, as the sequence correlates badly in large bases anyway.
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71,
+    73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+];
+
+impl HaltonSampler {
+    pub fn from(seed: u64) -> Self {
+        Self {
+            seed,
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, i: i32, j: i32, index: i32) {
+        self.pixel = (i, j);
+        self.index = index as u64;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let h = hash(&[
+            self.pixel.0 as u64,
+            self.pixel.1 as u64,
+            self.dimension as u64,
+            self.seed,
+        ]);
+        let u = match PRIMES.get(self.dimension) {
+            Some(&base) => owen_scrambled_radical_inverse(base, self.index, h),
+            None => {
+                let h = hash(&[h, self.index]);
+                (h >> 11) as f64 / (1u64 << 53) as f64
+            }
+        };
+        self.dimension += 1;
+        u
+    }
+}
+
+// Sobol points after Burley, "Practical Hash-based Owen Scrambling" (2020).
+// Every 2D sample comes from the first two Sobol dimensions, which form a
+// (0, 2)-sequence, Owen scrambled. The sample index is shuffled with a
+// different scramble for each dimension, which keeps the dimensions
+// independent of each other without tables for higher Sobol dimensions.
+// Works best with a power of two samples per pixel.
+pub struct SobolSampler {
+    seed: u64,
+    pixel: (i32, i32),
+    index: u32,
+    dimension: u64,
+}
+
+impl SobolSampler {
+    pub fn from(seed: u64) -> Self {
+        Self {
+            seed,
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn hashes(&mut self) -> (u32, u32, u32) {
+        // Seeds of the index shuffle and of the scramble of each coordinate,
+        // for the next dimension
+        let h = hash(&[
+            self.pixel.0 as u64,
+            self.pixel.1 as u64,
+            self.dimension,
+            self.seed,
+        ]);
+        self.dimension += 1;
+        (h as u32, (h >> 32) as u32, mix_bits(h) as u32)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, i: i32, j: i32, index: i32) {
+        self.pixel = (i, j);
+        self.index = index as u32;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let (shuffle, scramble, _) = self.hashes();
+        let index = nested_uniform_scramble(self.index, shuffle);
+        to_unit(nested_uniform_scramble(index.reverse_bits(), scramble))
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (shuffle, scramble_x, scramble_y) = self.hashes();
+        let index = nested_uniform_scramble(self.index, shuffle);
+        (
+            to_unit(nested_uniform_scramble(index.reverse_bits(), scramble_x)),
+            to_unit(nested_uniform_scramble(sobol_1(index), scramble_y)),
+        )
+    }
+}
+
+// Largest f64 below one
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+fn to_unit(bits: u32) -> f64 {
+    // Fixed point fraction in [0, 1)
+    bits as f64 / (1u64 << 32) as f64
+}
+
+fn sobol_1(index: u32) -> u32 {
+    // Second Sobol dimension, whose direction numbers each follow from the
+    // last with v ^= v >> 1
+    let mut v = 1u32 << 31;
+    let mut result = 0;
+    let mut index = index;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    // Owen scrambling of the bits of a fixed point fraction, most
+    // significant first, through the hash of Laine and Karras (2011) as
+    // improved by Burley
+    let mut x = x.reverse_bits();
+    x ^= x.wrapping_mul(0x3d20adea);
+    x = x.wrapping_add(seed);
+    x = x.wrapping_mul((seed >> 16) | 1);
+    x ^= x.wrapping_mul(0x05526c56);
+    x ^= x.wrapping_mul(0x53a22864);
+    x.reverse_bits()
+}
+
+fn owen_scrambled_radical_inverse(base: u64, index: u64, seed: u64) -> f64 {
+    // Digits of `index` in `base` mirrored about the radix point, each
+    // permuted depending on the digits before it
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_m = 1.0;
+    let mut reversed = 0u64;
+    let mut index = index;
+    // Digits past the precision of an f64 make no difference
+    while inv_base_m > f64::EPSILON {
+        let next = index / base;
+        let digit = index - next * base;
+        let digit_hash = mix_bits(seed ^ reversed);
+        let digit =
+            permutation_element(digit as u32, base as u32, digit_hash as u32);
+        reversed = reversed * base + digit as u64;
+        inv_base_m *= inv_base;
+        index = next;
+    }
+    (inv_base_m * reversed as f64).min(ONE_MINUS_EPSILON)
+}
+
+fn permutation_element(i: u32, l: u32, p: u32) -> u32 {
+    // Element `i` of a random permutation of 0..l chosen by `p`, from
+    // Kensler, "Correlated Multi-Jittered Sampling" (2013)
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    let mut i = i;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % l
+}
+
+pub fn hash(values: &[u64]) -> u64 {
+    // Combines the values into a well mixed seed, so that nearby inputs
+    // such as neighbouring pixels give unrelated streams
+    values.iter().fold(0x9e3779b97f4a7c15, |h, &v| {
+        mix_bits(h ^ mix_bits(v.wrapping_add(0x9e3779b97f4a7c15)))
+    })
+}
+
+fn mix_bits(v: u64) -> u64 {
+    // Finalizer of MurmurHash3
+    let mut v = v;
+    v ^= v >> 33;
+    v = v.wrapping_mul(0xff51afd7ed558ccd);
+    v ^= v >> 33;
+    v = v.wrapping_mul(0xc4ceb9fe1a85ec53);
+    v ^= v >> 33;
+    v
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::hittable::HittableList;
+    use crate::integrator::SimplePathTracer;
+    use crate::light::LightList;
+    use crate::material::{Dielectric, Lambertian};
+    use crate::sphere::Sphere;
+    use crate::vec3::{Color, Point3};
+    use std::rc::Rc;
+
+    fn numbers(
+        sampler: &mut dyn Sampler,
+        i: i32,
+        j: i32,
+        index: i32,
+    ) -> Vec<f64> {
+        sampler.start_pixel_sample(i, j, index);
+        (0..8).map(|_| sampler.get_1d()).collect()
+    }
+
+    #[test]
+    fn pixel_samples_are_reproducible() {
+        let mut sampler = IndependentSampler::from(7);
+        let first = numbers(&mut sampler, 3, 4, 5);
+        numbers(&mut sampler, 4, 3, 5);
+        assert_eq!(first, numbers(&mut sampler, 3, 4, 5));
+        assert_eq!(first, numbers(&mut IndependentSampler::from(7), 3, 4, 5));
+
+        // Other pixels, samples and seeds get their own numbers
+        assert_ne!(first, numbers(&mut sampler, 4, 3, 5));
+        assert_ne!(first, numbers(&mut sampler, 3, 4, 6));
+        assert_ne!(first, numbers(&mut IndependentSampler::from(8), 3, 4, 5));
+    }
+
+    fn samples(
+        sampler: &mut dyn Sampler,
+        count: i32,
+        dimension: usize,
+    ) -> Vec<(f64, (f64, f64))> {
+        // A 1D and a 2D sample from each of the first `count` samples of a
+        // pixel, after skipping `dimension` dimensions
+        (0..count)
+            .map(|index| {
+                sampler.start_pixel_sample(5, 9, index);
+                for _ in 0..dimension {
+                    sampler.get_1d();
+                }
+                (sampler.get_1d(), sampler.get_2d())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn samples_are_stratified() {
+        // 16 samples of a pixel cover each sixteenth of [0, 1) once, and
+        // each quarter of [0, 1)^2 four times, whatever the dimension
+        for sampling in [Sampling::Stratified, Sampling::Sobol] {
+            let mut sampler = sampling.sampler(16, 3);
+            for dimension in 0..40 {
+                let mut strata = [0; 16];
+                let mut quarters = [0; 4];
+                for (u, (x, y)) in samples(sampler.as_mut(), 16, dimension) {
+                    strata[(u * 16.0) as usize] += 1;
+                    quarters[(x * 2.0) as usize + 2 * (y * 2.0) as usize] += 1;
+                }
+                assert_eq!(strata, [1; 16], "{:?} {}", sampling, dimension);
+                assert_eq!(quarters, [4; 4], "{:?} {}", sampling, dimension);
+            }
+        }
+
+        // The first `base` Halton samples of a dimension are one in each
+        // `base`th of [0, 1)
+        let mut sampler = HaltonSampler::from(3);
+        for (dimension, &base) in PRIMES.iter().enumerate() {
+            let mut strata = vec![0; base as usize];
+            for (u, _) in samples(&mut sampler, base as i32, dimension) {
+                strata[(u * base as f64) as usize] += 1;
+            }
+            assert_eq!(strata, vec![1; base as usize], "{}", dimension);
+        }
+    }
+
+    #[test]
+    fn renders_are_reproducible() {
+        let mut world = HittableList::new();
+        world.add(Box::new(Sphere::from(
+            Point3::from(0.0, -100.5, -1.0),
+            100.0,
+            Rc::new(Lambertian::from(Color::from(0.5, 0.5, 0.5))),
+        )));
+        world.add(Box::new(Sphere::from(
+            Point3::from(0.0, 0.0, -1.0),
+            0.5,
+            Rc::new(Dielectric::from(1.5)),
+        )));
+
+        let render = |seed: u64, pass_samples: i32| {
+            let mut cam = Camera::new();
+            cam.image_width = 16;
+            cam.samples_per_pixel = 8;
+            cam.seed = seed;
+            cam.pass_samples = pass_samples;
+            let film = cam.render_film(
+                &world,
+                &LightList::new(),
+                &SimplePathTracer::new(),
+            );
+            let mut values = Vec::new();
+            for j in 0..film.height {
+                for i in 0..film.width {
+                    values.extend_from_slice(&film.value(i, j).e);
+                }
+            }
+            values
+        };
+        assert_eq!(render(1, 0), render(1, 0));
+        assert_ne!(render(1, 0), render(2, 0));
+
+        // Passes take the same samples as a single pass, just in a
+        // different order
+        assert_eq!(render(1, 0), render(1, 3));
+    }
+}
@@ -0,0 +1,287 @@
+use crate::ray::Ray;
+use crate::vec3::Color;
+
+// Range of wavelengths sampled in spectral mode, in nanometers
+pub const LAMBDA_MIN: f64 = 360.0;
+pub const LAMBDA_MAX: f64 = 830.0;
+
+// Wavelengths the channels of an RGB render stand for, where a material
+// needs one (the measured metal constants are given at these).
+pub const RGB_WAVELENGTHS: [f64; 3] = [650.0, 550.0, 450.0];
+
+// Integral of the y color matching function over the sampled range
+const CIE_Y_INTEGRAL: f64 = 106.922075;
+
+// Luminance of the D65 table below, relative to an equal energy spectrum
+const D65_Y: f64 = 98.852042;
+
+// Wavelengths carried by a path in spectral mode, after Wilkie et al.,
+// "Hero Wavelength Spectral Sampling" (2014). The first is sampled uniformly
+// and the others are spaced evenly after it, wrapping around the range. The
+// channels of every color along such a path hold values at these
+// wavelengths rather than RGB.
+#[derive(Debug, Copy, Clone)]
+pub struct Wavelengths {
+    lambda: [f64; 3],
+    // Set once the path has taken a direction that only suits the first
+    // (hero) wavelength, such as refraction through a dispersive medium.
+    // The other wavelengths then no longer count.
+    secondary_terminated: bool,
+}
+
+impl Wavelengths {
+    pub fn sample(u: f64) -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = LAMBDA_MIN + u * range;
+        let mut lambda = [hero; 3];
+        for (i, l) in lambda.iter_mut().enumerate() {
+            *l += i as f64 * range / 3.0;
+            if *l > LAMBDA_MAX {
+                *l -= range;
+            }
+        }
+        Self {
+            lambda,
+            secondary_terminated: false,
+        }
+    }
+
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    pub fn lambda(&self) -> [f64; 3] {
+        self.lambda
+    }
+
+    pub fn secondary_terminated(&self) -> bool {
+        self.secondary_terminated
+    }
+
+    pub fn terminate_secondary(&self) -> Self {
+        Self {
+            lambda: self.lambda,
+            secondary_terminated: true,
+        }
+    }
+
+    pub fn weight(&self) -> Color {
+        // Factor applied to a path's estimate once it's complete. After
+        // termination the hero wavelength stands in for all three; as each
+        // wavelength is equally likely to be the hero, this stays unbiased.
+        if self.secondary_terminated {
+            Color::from(3.0, 0.0, 0.0)
+        } else {
+            Color::from(1.0, 1.0, 1.0)
+        }
+    }
+
+    pub fn reflectance(&self, rgb: &Color) -> Color {
+        // Smooth spectrum with the given RGB color under a white light, for
+        // albedos and other unitless quantities
+        self.map(|l| smits(rgb, l))
+    }
+
+    pub fn illuminant(&self, rgb: &Color) -> Color {
+        // Emission spectrum with the given RGB color, white being D65 so
+        // that it matches the white point of sRGB
+        self.map(|l| smits(rgb, l) * d65(l) / D65_Y)
+    }
+
+    pub fn interpolate(&self, samples: &Color) -> Color {
+        // Spectrum through the values of an RGB triple taken as samples at
+        // `RGB_WAVELENGTHS`, held constant outside them
+        self.map(|l| {
+            let [red, green, blue] = RGB_WAVELENGTHS;
+            if l >= red {
+                samples.x()
+            } else if l >= green {
+                let t = (l - green) / (red - green);
+                samples.y() + t * (samples.x() - samples.y())
+            } else if l >= blue {
+                let t = (l - blue) / (green - blue);
+                samples.z() + t * (samples.y() - samples.z())
+            } else {
+                samples.z()
+            }
+        })
+    }
+
+    pub fn to_rgb(self, values: &Color) -> Color {
+        // Linear sRGB color of the spectral samples, through CIE XYZ
+        let pdf = 1.0 / (LAMBDA_MAX - LAMBDA_MIN);
+        let mut xyz = [0.0; 3];
+        for (i, l) in self.lambda.iter().enumerate() {
+            let value = values.e[i] / (3.0 * pdf * CIE_Y_INTEGRAL);
+            xyz[0] += value * cie_x(*l);
+            xyz[1] += value * cie_y(*l);
+            xyz[2] += value * cie_z(*l);
+        }
+        let [x, y, z] = xyz;
+        Color::from(
+            3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+            -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+            0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+        )
+    }
+
+    fn map<F: Fn(f64) -> f64>(&self, f: F) -> Color {
+        Color::from(f(self.lambda[0]), f(self.lambda[1]), f(self.lambda[2]))
+    }
+}
+
+// Conversions for quantities given in RGB, to the representation used by
+// the path `ray` belongs to: unchanged in RGB mode, sampled at the path's
+// wavelengths in spectral mode.
+
+pub fn reflectance(rgb: &Color, ray: &Ray) -> Color {
+    match ray.wavelengths() {
+        Some(wavelengths) => wavelengths.reflectance(rgb),
+        None => *rgb,
+    }
+}
+
+pub fn illuminant(rgb: &Color, ray: &Ray) -> Color {
+    match ray.wavelengths() {
+        Some(wavelengths) => wavelengths.illuminant(rgb),
+        None => *rgb,
+    }
+}
+
+pub fn interpolate(samples: &Color, ray: &Ray) -> Color {
+    match ray.wavelengths() {
+        Some(wavelengths) => wavelengths.interpolate(samples),
+        None => *samples,
+    }
+}
+
+pub fn resolve(values: &Color, ray: &Ray) -> Color {
+    // Estimate of a complete path ending with `ray`, once it is known
+    // whether its secondary wavelengths were terminated
+    match ray.wavelengths() {
+        Some(wavelengths) => wavelengths.weight() * *values,
+        None => *values,
+    }
+}
+
+pub fn to_rgb(values: &Color, wavelengths: Option<Wavelengths>) -> Color {
+    match wavelengths {
+        Some(wavelengths) => wavelengths.to_rgb(values),
+        None => *values,
+    }
+}
+
+pub fn channel_wavelengths(ray: &Ray) -> [f64; 3] {
+    // Wavelength each color channel stands for along `ray`
+    match ray.wavelengths() {
+        Some(wavelengths) => wavelengths.lambda(),
+        None => RGB_WAVELENGTHS,
+    }
+}
+
+// Smits, "An RGB-to-Spectrum Conversion for Reflectances" (1999): spectra
+// of the primaries and their mixtures, in ten bins over 380-720nm.
+const SMITS_WHITE: [f64; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000,
+    1.0000,
+];
+const SMITS_CYAN: [f64; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000,
+    0.0000,
+];
+const SMITS_MAGENTA: [f64; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000,
+    0.9959,
+];
+const SMITS_YELLOW: [f64; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685,
+    0.9840,
+];
+const SMITS_RED: [f64; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149,
+    1.0149,
+];
+const SMITS_GREEN: [f64; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000,
+    0.0025,
+];
+const SMITS_BLUE: [f64; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483,
+    0.0496,
+];
+
+fn smits(rgb: &Color, lambda: f64) -> f64 {
+    let bin = (((lambda - 380.0) / 34.0) as i32).clamp(0, 9) as usize;
+    let (r, g, b) = (rgb.x(), rgb.y(), rgb.z());
+
+    // The smallest component is white, the middle one the mixture of the
+    // other two, and the rest the largest primary
+    if r <= g && r <= b {
+        let white = r * SMITS_WHITE[bin];
+        if g <= b {
+            white + (g - r) * SMITS_CYAN[bin] + (b - g) * SMITS_BLUE[bin]
+        } else {
+            white + (b - r) * SMITS_CYAN[bin] + (g - b) * SMITS_GREEN[bin]
+        }
+    } else if g <= r && g <= b {
+        let white = g * SMITS_WHITE[bin];
+        if r <= b {
+            white + (r - g) * SMITS_MAGENTA[bin] + (b - r) * SMITS_BLUE[bin]
+        } else {
+            white + (b - g) * SMITS_MAGENTA[bin] + (r - b) * SMITS_RED[bin]
+        }
+    } else {
+        let white = b * SMITS_WHITE[bin];
+        if r <= g {
+            white + (r - b) * SMITS_YELLOW[bin] + (g - r) * SMITS_GREEN[bin]
+        } else {
+            white + (g - b) * SMITS_YELLOW[bin] + (r - g) * SMITS_RED[bin]
+        }
+    }
+}
+
+// CIE standard illuminant D65, every 10nm from 380nm to 780nm
+const D65: [f64; 41] = [
+    49.98, 54.65, 82.75, 91.49, 93.43, 86.68, 104.86, 117.01, 117.81, 114.86,
+    115.92, 108.81, 109.35, 107.80, 104.79, 107.69, 104.41, 104.05, 100.00,
+    96.33, 95.79, 88.69, 90.01, 89.60, 87.70, 83.29, 83.70, 80.03, 80.21,
+    82.28, 78.28, 69.72, 71.61, 74.35, 61.60, 69.89, 75.09, 63.59, 46.42,
+    66.81, 63.38,
+];
+
+fn d65(lambda: f64) -> f64 {
+    let x = ((lambda - 380.0) / 10.0).clamp(0.0, 40.0);
+    let i = (x as usize).min(39);
+    let t = x - i as f64;
+    D65[i] + t * (D65[i + 1] - D65[i])
+}
+
+// CIE 1931 color matching functions, using the piecewise Gaussian fit of
+// Wyman et al., "Simple Analytic Approximations to the CIE XYZ Color
+// Matching Functions" (2013).
+
+fn gaussian(lambda: f64, mu: f64, sigma_below: f64, sigma_above: f64) -> f64 {
+    let sigma = if lambda < mu {
+        sigma_below
+    } else {
+        sigma_above
+    };
+    let t = (lambda - mu) / sigma;
+    (-0.5 * t * t).exp()
+}
+
+fn cie_x(lambda: f64) -> f64 {
+    1.056 * gaussian(lambda, 599.8, 37.9, 31.0)
+        + 0.362 * gaussian(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * gaussian(lambda, 501.1, 20.4, 26.2)
+}
+
+fn cie_y(lambda: f64) -> f64 {
+    0.821 * gaussian(lambda, 568.8, 46.9, 40.5)
+        + 0.286 * gaussian(lambda, 530.9, 16.3, 31.1)
+}
+
+fn cie_z(lambda: f64) -> f64 {
+    1.217 * gaussian(lambda, 437.0, 11.8, 36.0)
+        + 0.681 * gaussian(lambda, 459.0, 26.0, 13.8)
+}
@@ -5,6 +5,7 @@ use crate::material::Material;
 use crate::ray::Ray;
 use crate::vec3;
 use crate::vec3::{Point3, Vec3};
+use std::f64::consts::PI;
 use std::rc::Rc;
 
 pub struct Sphere {
@@ -28,6 +29,16 @@ impl Sphere {
             mat: Some(mat),
         }
     }
+
+    fn get_sphere_uv(p: &Point3) -> (f64, f64) {
+        // p: a given point on the sphere of radius one, centered at the origin.
+        // u: returned value [0,1] of angle around the Y axis from X=-1.
+        // v: returned value [0,1] of angle from Y=-1 to Y=+1.
+        let theta = (-p.y()).acos();
+        let phi = (-p.z()).atan2(p.x()) + PI;
+
+        (phi / (2.0 * PI), theta / PI)
+    }
 }
 
 impl Hittable for Sphere {
@@ -66,6 +77,7 @@ impl Hittable for Sphere {
 
         let outward_normal = (rec.p - self.center) / self.radius;
         rec.set_face_normal(ray, outward_normal);
+        (rec.u, rec.v) = Self::get_sphere_uv(&outward_normal);
         if let Some(mat) = &self.mat {
             rec.mat = Some(mat.clone());
         }
@@ -0,0 +1,466 @@
+use crate::camera::Camera;
+use crate::film::Film;
+use crate::hittable;
+use crate::hittable::{HitRecord, Hittable, HittableList};
+use crate::integrator;
+use crate::light::LightList;
+use crate::ray::Ray;
+use crate::sampler;
+use crate::sampler::{IndependentSampler, Sampler};
+use crate::utils;
+use crate::vec3;
+use crate::vec3::{Color, Point3, Vec3};
+
+use std::f64::consts::PI;
+
+// Stochastic progressive photon mapping, after Hachisuka and Jensen (2009).
+// Each iteration traces one camera path per pixel to its first non-specular
+// hit, then shoots photons from the lights and gathers those landing near
+// each of these visible points. The gather radius shrinks as photons
+// accumulate, so the estimate converges to the right answer, and caustics
+// through specular surfaces come out sharp.
+//
+// Only the lights in the light list emit photons. The background lights
+// the visible points directly, but light from it reaching them after a
+// bounce is missing, so sky lit scenes come out darker than with the path
+// tracer.
+pub struct Sppm {
+    pub iterations: i32,
+    pub photons_per_iteration: usize,
+    // Starting gather radius, in scene units
+    pub initial_radius: f64,
+    pub max_depth: i32,
+}
+
+// Fraction of the new photons kept at each radius reduction
+const ALPHA: f64 = 2.0 / 3.0;
+
+struct VisiblePoint {
+    rec: HitRecord,
+    r_in: Ray,
+    // Throughput of the camera path up to the point
+    beta: Color,
+}
+
+struct Pixel {
+    radius: f64,
+    // Light reaching the camera other than by photons: emission, direct
+    // lighting and the background
+    ld: Color,
+    vp: Option<VisiblePoint>,
+    // Photon flux gathered in this iteration, and how many photons
+    phi: Color,
+    m: usize,
+    // Accumulated photon count and flux over previous iterations
+    n: f64,
+    tau: Color,
+}
+
+#[derive(Copy, Clone)]
+struct Photon {
+    p: Point3,
+    // Direction the photon arrived from
+    wi: Vec3,
+    power: Color,
+}
+
+impl Sppm {
+    pub fn new() -> Self {
+        Self {
+            iterations: 64,
+            photons_per_iteration: 100_000,
+            initial_radius: 0.1,
+            max_depth: 10,
+        }
+    }
+
+    pub fn render_film(
+        &self,
+        camera: &mut Camera,
+        world: &HittableList,
+        lights: &LightList,
+    ) -> Film {
+        camera.initialize();
+        let width = camera.image_width;
+        let height = camera.image_height();
+
+        let mut pixels: Vec<Pixel> = (0..width * height)
+            .map(|_| Pixel {
+                radius: self.initial_radius,
+                ld: Color::new(),
+                vp: None,
+                phi: Color::new(),
+                m: 0,
+                n: 0.0,
+                tau: Color::new(),
+            })
+            .collect();
+        let mut sampler = camera.sampling.sampler(self.iterations, camera.seed);
+
+        for iteration in 0..self.iterations {
+            log::info!(
+                r"Iterations remaining: {} ",
+                self.iterations - iteration
+            );
+
+            for j in 0..height {
+                for i in 0..width {
+                    let pixel = &mut pixels[(j * width + i) as usize];
+                    sampler.start_pixel_sample(i, j, iteration);
+                    let ray = camera.get_ray(i, j, sampler.as_mut());
+                    let (ld, vp) = self.visible_point(
+                        &ray,
+                        camera,
+                        world,
+                        lights,
+                        sampler.as_mut(),
+                    );
+                    pixel.ld += ld;
+                    pixel.vp = vp;
+                }
+            }
+
+            // Each iteration's photons get their own stream
+            let mut photon_sampler =
+                IndependentSampler::from(sampler::hash(&[
+                    camera.seed,
+                    iteration as u64,
+                ]));
+            let photons = PhotonMap::from(self.trace_photons(
+                world,
+                lights,
+                &mut photon_sampler,
+            ));
+            for pixel in pixels.iter_mut() {
+                self.gather(pixel, &photons);
+            }
+        }
+
+        // Radiance estimate from the flux gathered within the final radius
+        let mut film = camera.film();
+        let photons =
+            self.iterations as f64 * self.photons_per_iteration as f64;
+        for j in 0..height {
+            for i in 0..width {
+                let pixel = &pixels[(j * width + i) as usize];
+                let area = PI * pixel.radius * pixel.radius;
+                let l = pixel.ld / self.iterations as f64
+                    + pixel.tau / (photons * area);
+                film.add_sample(i, j, l);
+            }
+        }
+        film
+    }
+
+    fn visible_point(
+        &self,
+        ray: &Ray,
+        camera: &Camera,
+        world: &HittableList,
+        lights: &LightList,
+        sampler: &mut dyn Sampler,
+    ) -> (Color, Option<VisiblePoint>) {
+        // Follows specular bounces from the camera to the first surface
+        // that photons can be gathered on. Returns the light found along the
+        // way and the point, if any.
+        let mut ld = Color::new();
+        let mut beta = Color::from(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+
+        for _ in 0..self.max_depth {
+            let mut rec = HitRecord::new();
+            if !world.hit(
+                &ray,
+                hittable::Interval::from(0.001, f64::INFINITY),
+                &mut rec,
+            ) {
+                ld += beta * camera.background.value(&ray);
+                break;
+            }
+            let material = match rec.mat.clone() {
+                Some(material) => material,
+                None => break,
+            };
+
+            // Every earlier bounce was specular, so emission counts in full
+            ld += beta * material.emitted(&ray, &rec);
+
+            if !material.is_specular() {
+                ld += beta
+                    * self.direct_lighting(
+                        &ray, &rec, camera, world, lights, sampler,
+                    );
+                let vp = VisiblePoint {
+                    rec,
+                    r_in: ray,
+                    beta,
+                };
+                return (ld, Some(vp));
+            }
+
+            let srec = match material.sample(&ray, &rec, sampler) {
+                Some(srec) => srec,
+                None => break,
+            };
+            beta = beta * srec.attenuation;
+            ray = srec.scattered;
+        }
+        (ld, None)
+    }
+
+    fn direct_lighting(
+        &self,
+        ray: &Ray,
+        rec: &HitRecord,
+        camera: &Camera,
+        world: &HittableList,
+        lights: &LightList,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        // Light sampling plus one BSDF sample, combined with MIS. Photons
+        // only account for light that bounced at least once.
+        let material = match &rec.mat {
+            Some(material) => material.as_ref(),
+            None => return Color::new(),
+        };
+        let mut color = integrator::sample_light(
+            ray, rec, material, world, lights, sampler,
+        );
+
+        let srec = match material.sample(ray, rec, sampler) {
+            Some(srec) => srec,
+            None => return color,
+        };
+        if srec.is_specular {
+            return color;
+        }
+        let mut light_rec = HitRecord::new();
+        if !world.hit(
+            &srec.scattered,
+            hittable::Interval::from(0.001, f64::INFINITY),
+            &mut light_rec,
+        ) {
+            return color
+                + srec.attenuation * camera.background.value(&srec.scattered);
+        }
+        if let Some(light_material) = &light_rec.mat {
+            let light_pdf = lights.pdf_li(
+                &rec.p,
+                &srec.scattered.direction(),
+                &light_rec.p,
+            );
+            let weight = utils::power_heuristic(srec.pdf, light_pdf);
+            color += weight
+                * (srec.attenuation
+                    * light_material.emitted(&srec.scattered, &light_rec));
+        }
+        color
+    }
+
+    fn trace_photons(
+        &self,
+        world: &HittableList,
+        lights: &LightList,
+        sampler: &mut dyn Sampler,
+    ) -> Vec<Photon> {
+        let mut photons = Vec::new();
+        for _ in 0..self.photons_per_iteration {
+            let (light, pmf) =
+                match lights.sample_emitter(utils::random_double(sampler)) {
+                    Some(choice) => choice,
+                    None => break,
+                };
+            let le = match light.sample_le(sampler) {
+                Some(le) => le,
+                None => continue,
+            };
+            if le.pdf_pos == 0.0 || le.pdf_dir == 0.0 {
+                continue;
+            }
+            let cos_theta = if le.n.near_zero() {
+                1.0
+            } else {
+                vec3::dot(&le.n, &le.ray.direction()).abs()
+            };
+            let mut beta =
+                (cos_theta / (pmf * le.pdf_pos * le.pdf_dir)) * le.radiance;
+            let mut ray = le.ray;
+
+            for depth in 0..self.max_depth {
+                let mut rec = HitRecord::new();
+                if !world.hit(
+                    &ray,
+                    hittable::Interval::from(0.001, f64::INFINITY),
+                    &mut rec,
+                ) {
+                    break;
+                }
+                let material = match rec.mat.clone() {
+                    Some(material) => material,
+                    None => break,
+                };
+
+                // Direct lighting is estimated separately at the visible
+                // points, so only photons that have bounced are stored
+                if depth > 0 && !material.is_specular() {
+                    photons.push(Photon {
+                        p: rec.p,
+                        wi: -vec3::unit_vector(ray.direction()),
+                        power: beta,
+                    });
+                }
+
+                let srec = match material.sample(&ray, &rec, sampler) {
+                    Some(srec) => srec,
+                    None => break,
+                };
+                let new_beta = beta * srec.attenuation;
+                if max_component(&new_beta) <= 0.0 {
+                    break;
+                }
+
+                // Russian roulette, keeping the photon power roughly constant
+                let survive =
+                    (max_component(&new_beta) / max_component(&beta)).min(1.0);
+                if utils::random_double(sampler) >= survive {
+                    break;
+                }
+                beta = new_beta / survive;
+                ray = srec.scattered;
+            }
+        }
+        photons
+    }
+
+    fn gather(&self, pixel: &mut Pixel, photons: &PhotonMap) {
+        if let Some(vp) = &pixel.vp {
+            let material = match &vp.rec.mat {
+                Some(material) => material,
+                None => return,
+            };
+            photons.for_each_within(&vp.rec.p, pixel.radius, |photon| {
+                // `eval` includes the cosine, which the density estimate
+                // already accounts for
+                let cos_theta = vec3::dot(&vp.rec.normal, &photon.wi).abs();
+                if cos_theta < 1e-6 {
+                    return;
+                }
+                let f = material.eval(&vp.r_in, &vp.rec, &photon.wi);
+                pixel.phi += (f / cos_theta) * photon.power;
+                pixel.m += 1;
+            });
+        }
+
+        // Shrink the radius so that the density estimate stays consistent
+        if pixel.m > 0 {
+            let beta = match &pixel.vp {
+                Some(vp) => vp.beta,
+                None => Color::new(),
+            };
+            let m = pixel.m as f64;
+            let n = pixel.n + ALPHA * m;
+            let radius = pixel.radius * (n / (pixel.n + m)).sqrt();
+            let shrink = (radius * radius) / (pixel.radius * pixel.radius);
+            pixel.tau = shrink * (pixel.tau + beta * pixel.phi);
+            pixel.n = n;
+            pixel.radius = radius;
+            pixel.m = 0;
+            pixel.phi = Color::new();
+        }
+    }
+}
+
+fn max_component(c: &Color) -> f64 {
+    c.x().max(c.y()).max(c.z())
+}
+
+// Balanced kd-tree over photon positions, stored implicitly: the median of
+// each range is its node, with the lower half to the left.
+struct PhotonMap {
+    photons: Vec<Photon>,
+    // Split axis of the node at each index
+    axes: Vec<usize>,
+}
+
+impl PhotonMap {
+    fn from(photons: Vec<Photon>) -> Self {
+        let mut map = Self {
+            axes: vec![0; photons.len()],
+            photons,
+        };
+        let len = map.photons.len();
+        map.build(0, len);
+        map
+    }
+
+    fn build(&mut self, lo: usize, hi: usize) {
+        if hi - lo <= 1 {
+            return;
+        }
+
+        // Split along the axis with the widest spread of positions
+        let mut min = self.photons[lo].p;
+        let mut max = self.photons[lo].p;
+        for photon in &self.photons[lo..hi] {
+            for k in 0..3 {
+                min.e[k] = min.e[k].min(photon.p.e[k]);
+                max.e[k] = max.e[k].max(photon.p.e[k]);
+            }
+        }
+        let extent = max - min;
+        let axis = if extent.x() > extent.y() && extent.x() > extent.z() {
+            0
+        } else if extent.y() > extent.z() {
+            1
+        } else {
+            2
+        };
+
+        let mid = (lo + hi) / 2;
+        self.photons[lo..hi].select_nth_unstable_by(mid - lo, |a, b| {
+            a.p.e[axis].total_cmp(&b.p.e[axis])
+        });
+        self.axes[mid] = axis;
+        self.build(lo, mid);
+        self.build(mid + 1, hi);
+    }
+
+    fn for_each_within<F: FnMut(&Photon)>(
+        &self,
+        p: &Point3,
+        radius: f64,
+        mut f: F,
+    ) {
+        self.visit(0, self.photons.len(), p, radius, &mut f);
+    }
+
+    fn visit<F: FnMut(&Photon)>(
+        &self,
+        lo: usize,
+        hi: usize,
+        p: &Point3,
+        radius: f64,
+        f: &mut F,
+    ) {
+        if lo >= hi {
+            return;
+        }
+        let mid = (lo + hi) / 2;
+        let photon = &self.photons[mid];
+        if (photon.p - *p).length_squared() <= radius * radius {
+            f(photon);
+        }
+        if hi - lo == 1 {
+            return;
+        }
+
+        let axis = self.axes[mid];
+        let d = p.e[axis] - photon.p.e[axis];
+        if d <= radius {
+            self.visit(lo, mid, p, radius, f);
+        }
+        if d >= -radius {
+            self.visit(mid + 1, hi, p, radius, f);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use crate::vec3;
+use crate::vec3::Color;
+
+// Operator compressing the unbounded radiance of a render into the range a
+// display can show. Each maps linear sRGB to linear sRGB, before the sRGB
+// transfer function is applied for output.
+#[derive(Debug, Copy, Clone)]
+pub enum ToneMap {
+    // No compression: everything brighter than white is clipped
+    Clamp,
+    // Reinhard et al., "Photographic Tone Reproduction for Digital Images"
+    // (2002), applied to luminance so that hues are kept
+    Reinhard,
+    // Reinhard with a white point: luminance `white` and above maps to white
+    ExtendedReinhard { white: f64 },
+    // Hill's fit of the ACES reference rendering and sRGB output transforms,
+    // a filmic curve that desaturates toward white
+    Aces,
+    // Sobotka's AgX, through Wrensch's polynomial fit, which handles very
+    // bright saturated colors more gracefully than ACES
+    Agx,
+}
+
+impl ToneMap {
+    pub fn apply(&self, color: &Color) -> Color {
+        match *self {
+            Self::Clamp => *color,
+            Self::Reinhard => scale_luminance(color, |l| l / (1.0 + l)),
+            Self::ExtendedReinhard { white } => scale_luminance(color, |l| {
+                l * (1.0 + l / (white * white)) / (1.0 + l)
+            }),
+            Self::Aces => {
+                let v = multiply(&ACES_INPUT, color);
+                let v = Color::from(
+                    rrt_and_odt(v.x()),
+                    rrt_and_odt(v.y()),
+                    rrt_and_odt(v.z()),
+                );
+                multiply(&ACES_OUTPUT, &v)
+            }
+            Self::Agx => {
+                let v = multiply(&AGX_INSET, color);
+                let v = Color::from(
+                    agx_curve(v.x()),
+                    agx_curve(v.y()),
+                    agx_curve(v.z()),
+                );
+                let v = multiply(&AGX_OUTSET, &v);
+                // The curve's output is display encoded with a 2.2 gamma
+                Color::from(
+                    v.x().max(0.0).powf(2.2),
+                    v.y().max(0.0).powf(2.2),
+                    v.z().max(0.0).powf(2.2),
+                )
+            }
+        }
+    }
+}
+
+fn scale_luminance<F: Fn(f64) -> f64>(color: &Color, f: F) -> Color {
+    let l = vec3::luminance(color);
+    if l <= 0.0 {
+        return Color::new();
+    }
+    (f(l) / l) * *color
+}
+
+fn multiply(m: &[[f64; 3]; 3], v: &Color) -> Color {
+    let row = |r: &[f64; 3]| r[0] * v.x() + r[1] * v.y() + r[2] * v.z();
+    Color::from(row(&m[0]), row(&m[1]), row(&m[2]))
+}
+
+// From linear sRGB into the ACES rendering space, including the reference
+// rendering transform's exposure and saturation adjustments, and back
+const ACES_INPUT: [[f64; 3]; 3] = [
+    [0.59719, 0.35458, 0.04823],
+    [0.07600, 0.90834, 0.01566],
+    [0.02840, 0.13383, 0.83777],
+];
+const ACES_OUTPUT: [[f64; 3]; 3] = [
+    [1.60475, -0.53108, -0.07367],
+    [-0.10208, 1.10813, -0.00605],
+    [-0.00327, -0.07276, 1.07602],
+];
+
+fn rrt_and_odt(v: f64) -> f64 {
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    a / b
+}
+
+// Into AgX's working space, whose primaries are pulled in toward white so
+// that bright saturated colors fade to white, and back
+const AGX_INSET: [[f64; 3]; 3] = [
+    [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+    [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+    [0.0423756549057051, 0.0784336, 0.879142973793104],
+];
+const AGX_OUTSET: [[f64; 3]; 3] = [
+    [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+    [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+    [-0.0529716355144438, -0.0980434501171241, 1.15107367264116],
+];
+
+// Range of stops around middle grey the curve covers
+const AGX_MIN_EV: f64 = -12.47393;
+const AGX_MAX_EV: f64 = 4.026069;
+
+fn agx_curve(v: f64) -> f64 {
+    // Sigmoid over the logarithm of the value
+    let x = (v.max(1e-10).log2().clamp(AGX_MIN_EV, AGX_MAX_EV) - AGX_MIN_EV)
+        / (AGX_MAX_EV - AGX_MIN_EV);
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x
+        + 0.4298 * x2
+        + 0.1191 * x
+        - 0.00232
+}
@@ -1,21 +1,31 @@
-use rand::prelude::*;
+use crate::sampler::Sampler;
 
 pub fn degrees_to_radians(degrees: f64) -> f64 {
     return degrees * std::f64::consts::PI / 180.0;
 }
 
-pub fn random_double() -> f64 {
+pub fn random_double(sampler: &mut dyn Sampler) -> f64 {
     //Returns a random real in [0,1).
 
-    let mut rng = rand::thread_rng();
-
-    return rng.gen();
+    sampler.get_1d()
 }
 
-pub fn random_double_in(min: f64, max: f64) -> f64 {
+pub fn random_double_in(min: f64, max: f64, sampler: &mut dyn Sampler) -> f64 {
     //Returns a random real in [min,max).
 
-    let mut rng = rand::thread_rng();
+    min + (max - min) * sampler.get_1d()
+}
 
-    return rng.gen_range(min..max);
+pub fn power_heuristic(f_pdf: f64, g_pdf: f64) -> f64 {
+    // Multiple importance sampling weight of a sample drawn from f, when g
+    // could also have produced it (Veach's power heuristic, beta = 2).
+    let f = f_pdf * f_pdf;
+    let g = g_pdf * g_pdf;
+    if f.is_infinite() {
+        return 1.0;
+    }
+    if f + g == 0.0 {
+        return 0.0;
+    }
+    f / (f + g)
 }
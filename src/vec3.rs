@@ -1,6 +1,7 @@
 use std::ops;
 
 use crate::hittable::Interval;
+use crate::sampler::Sampler;
 #[derive(Debug, Copy, Clone)]
 pub struct Vec3 {
     pub e: [f64; 3],
@@ -40,19 +41,19 @@ impl Vec3 {
         return self.length_squared().sqrt();
     }
 
-    pub fn random() -> Self {
+    pub fn random(sampler: &mut dyn Sampler) -> Self {
         return Self::from(
-            crate::utils::random_double(),
-            crate::utils::random_double(),
-            crate::utils::random_double(),
+            crate::utils::random_double(sampler),
+            crate::utils::random_double(sampler),
+            crate::utils::random_double(sampler),
         );
     }
 
-    pub fn random_in(min: f64, max: f64) -> Vec3 {
+    pub fn random_in(min: f64, max: f64, sampler: &mut dyn Sampler) -> Vec3 {
         return Self::from(
-            crate::utils::random_double_in(min, max),
-            crate::utils::random_double_in(min, max),
-            crate::utils::random_double_in(min, max),
+            crate::utils::random_double_in(min, max, sampler),
+            crate::utils::random_double_in(min, max, sampler),
+            crate::utils::random_double_in(min, max, sampler),
         );
     }
 
@@ -65,21 +66,30 @@ impl Vec3 {
     }
 }
 
-fn random_in_unit_sphere() -> Vec3 {
-    loop {
-        let p = Vec3::random_in(-1.0, 1.0);
-        if p.length_squared() < 1.0 {
-            return p;
-        }
-    }
+pub fn random_unit_vector(sampler: &mut dyn Sampler) -> Vec3 {
+    // Uniform on the sphere: z is uniform in [-1, 1]
+    let (u1, u2) = sampler.get_2d();
+    let z = 1.0 - 2.0 * u1;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * std::f64::consts::PI * u2;
+
+    Vec3::from(r * phi.cos(), r * phi.sin(), z)
 }
 
-pub fn random_unit_vector() -> Vec3 {
-    return unit_vector(random_in_unit_sphere());
+pub fn random_cosine_direction(sampler: &mut dyn Sampler) -> Vec3 {
+    // Cosine weighted direction around +z
+    let (r1, r2) = sampler.get_2d();
+
+    let phi = 2.0 * std::f64::consts::PI * r1;
+    let x = phi.cos() * r2.sqrt();
+    let y = phi.sin() * r2.sqrt();
+    let z = (1.0 - r2).sqrt();
+
+    Vec3::from(x, y, z)
 }
 
-pub fn random_on_hemisphere(normal: &Vec3) -> Vec3 {
-    let on_unit_sphere = random_in_unit_sphere();
+pub fn random_on_hemisphere(normal: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+    let on_unit_sphere = random_unit_vector(sampler);
 
     if dot(&on_unit_sphere, normal) > 0.0 {
         return on_unit_sphere;
@@ -106,37 +116,40 @@ pub fn unit_vector(v: Vec3) -> Vec3 {
     return v / k;
 }
 
+pub fn luminance(c: &Color) -> f64 {
+    // Relative luminance of a linear sRGB color
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
+
 fn linear_to_gamma(linear_component: f64) -> f64 {
-    return linear_component.sqrt();
+    // sRGB transfer function
+    if linear_component <= 0.0031308 {
+        12.92 * linear_component
+    } else {
+        1.055 * linear_component.powf(1.0 / 2.4) - 0.055
+    }
 }
 
-pub fn write_color<W: std::io::Write>(
-    mut out: W,
-    pixel_color: &Color,
-    samples_per_pixel: i32,
-) {
-    // Divide the color by the number of samples
-    let scale = 1.0 / samples_per_pixel as f64;
-
-    let mut r = pixel_color.x() * scale;
-    let mut g = pixel_color.y() * scale;
-    let mut b = pixel_color.z() * scale;
-
-    // Gamma corrected colors
-    r = linear_to_gamma(r);
-    g = linear_to_gamma(g);
-    b = linear_to_gamma(b);
-
-    let mut intensity = Interval::from(0.0, 1.0);
-
-    write!(
-        &mut out,
-        "{} {} {} \n",
-        (256.0 * intensity.clamp(r)) as i32,
-        (256.0 * intensity.clamp(g)) as i32,
-        (256.0 * intensity.clamp(b)) as i32
-    )
-    .expect("Failed to write to out in print_color");
+pub fn write_color<W: std::io::Write>(mut out: W, pixel_color: &Color) {
+    // Writes a linear color, already in the display's range
+    let [r, g, b] = to_bytes(pixel_color);
+    write!(&mut out, "{} {} {} \n", r, g, b)
+        .expect("Failed to write to out in print_color");
+}
+
+pub fn to_bytes(pixel_color: &Color) -> [u8; 3] {
+    // 8 bit sRGB encoding of a linear color in the display's range
+    let r = linear_to_gamma(pixel_color.x());
+    let g = linear_to_gamma(pixel_color.y());
+    let b = linear_to_gamma(pixel_color.z());
+
+    let mut intensity = Interval::from(0.0, 0.999);
+
+    [
+        (256.0 * intensity.clamp(r)) as u8,
+        (256.0 * intensity.clamp(g)) as u8,
+        (256.0 * intensity.clamp(b)) as u8,
+    ]
 }
 
 pub fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
@@ -152,17 +165,21 @@ pub fn refract(uv: &Vec3, n: &Vec3, etai_over_etat: f64) -> Vec3 {
     return r_out_perp + r_out_parallel;
 }
 
-pub fn random_in_unit_disk() -> Vec3 {
-    loop {
-        let p = Vec3::from(
-            crate::utils::random_double_in(-1.0, 1.0),
-            crate::utils::random_double_in(-1.0, 1.0),
-            0.0,
-        );
-        if p.length_squared() < 1.0 {
-            return p;
-        }
+pub fn random_in_unit_disk(sampler: &mut dyn Sampler) -> Vec3 {
+    // Shirley and Chiu's concentric mapping of the square onto the disk,
+    // which keeps stratified samples stratified
+    let (u1, u2) = sampler.get_2d();
+    let (x, y) = (2.0 * u1 - 1.0, 2.0 * u2 - 1.0);
+    if x == 0.0 && y == 0.0 {
+        return Vec3::new();
     }
+    let quarter_pi = std::f64::consts::FRAC_PI_4;
+    let (r, theta) = if x.abs() > y.abs() {
+        (x, quarter_pi * (y / x))
+    } else {
+        (y, 2.0 * quarter_pi - quarter_pi * (x / y))
+    };
+    Vec3::from(r * theta.cos(), r * theta.sin(), 0.0)
 }
 
 //Operator overloading